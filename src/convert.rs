@@ -0,0 +1,356 @@
+// Conversions between Rust values and mal values, used to write builtins
+// and to exchange data with programs embedding mal
+
+use crate::printer::prt;
+use crate::types::{Frac, MalErr, MalStr, MalType, NativeFn};
+use std::collections::HashMap;
+
+use MalType as M;
+
+/// Rust values that can be extracted from a mal value
+pub trait FromMal: Sized {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr>;
+}
+
+/// Rust values that can be turned into a mal value
+pub trait IntoMal {
+    fn into_mal(self) -> MalType;
+}
+
+fn mismatch(value: &MalType, expected: &str) -> MalErr {
+    MalErr::unrecoverable(format!("{:?} is not {expected}", prt(value)).as_str())
+}
+
+impl FromMal for MalType {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        Ok(value.clone())
+    }
+}
+
+impl IntoMal for MalType {
+    fn into_mal(self) -> MalType {
+        self
+    }
+}
+
+impl IntoMal for NativeFn {
+    fn into_mal(self) -> MalType {
+        self.into()
+    }
+}
+
+impl IntoMal for () {
+    fn into_mal(self) -> MalType {
+        M::Nil
+    }
+}
+
+impl FromMal for Frac {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        value.if_number()
+    }
+}
+
+impl IntoMal for Frac {
+    fn into_mal(self) -> MalType {
+        M::Num(self)
+    }
+}
+
+// Integers only accept exact values with no fractional part
+macro_rules! integer_conversions {
+    ($($int:ty),*) => {$(
+        impl FromMal for $int {
+            fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+                let int = value.if_number()?.to_i128().ok_or_else(|| mismatch(value, "an integer"))?;
+                int.try_into().map_err(|_| {
+                    MalErr::unrecoverable(
+                        format!("{int} does not fit in {}", stringify!($int)).as_str(),
+                    )
+                })
+            }
+        }
+
+        impl IntoMal for $int {
+            fn into_mal(self) -> MalType {
+                M::Num(Frac::integer(self as i128))
+            }
+        }
+    )*};
+}
+
+integer_conversions!(i8, i16, i32, i64, i128, isize, u8, u16, u32, u64, usize);
+
+// Floating point values accept any number, and always produce inexact ones
+macro_rules! float_conversions {
+    ($($float:ty),*) => {$(
+        impl FromMal for $float {
+            fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+                Ok(value.if_number()?.to_f64() as $float)
+            }
+        }
+
+        impl IntoMal for $float {
+            fn into_mal(self) -> MalType {
+                M::Num(Frac::inexact(self as f64))
+            }
+        }
+    )*};
+}
+
+float_conversions!(f32, f64);
+
+// Anything but nil is true
+impl FromMal for bool {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        Ok(!matches!(value, M::Nil))
+    }
+}
+
+impl IntoMal for bool {
+    fn into_mal(self) -> MalType {
+        if self {
+            M::T
+        } else {
+            M::Nil
+        }
+    }
+}
+
+impl FromMal for char {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        match value {
+            M::Ch(c) => Ok(*c),
+            _ => Err(mismatch(value, "a char")),
+        }
+    }
+}
+
+impl IntoMal for char {
+    fn into_mal(self) -> MalType {
+        M::Ch(self)
+    }
+}
+
+impl FromMal for String {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        Ok(value.if_string()?.to_string())
+    }
+}
+
+impl FromMal for MalStr {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        Ok(value.if_string()?.into())
+    }
+}
+
+impl IntoMal for String {
+    fn into_mal(self) -> MalType {
+        M::Str(self.into())
+    }
+}
+
+impl IntoMal for &str {
+    fn into_mal(self) -> MalType {
+        M::Str(self.into())
+    }
+}
+
+impl IntoMal for MalStr {
+    fn into_mal(self) -> MalType {
+        M::Str(self)
+    }
+}
+
+// nil is None, anything else must convert to T
+impl<T: FromMal> FromMal for Option<T> {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        match value {
+            M::Nil => Ok(None),
+            _ => T::from_mal(value).map(Some),
+        }
+    }
+}
+
+impl<T: IntoMal> IntoMal for Option<T> {
+    fn into_mal(self) -> MalType {
+        self.map_or(M::Nil, T::into_mal)
+    }
+}
+
+// Both lists and vectors are accepted, lists are produced
+impl<T: FromMal> FromMal for Vec<T> {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        value.if_list()?.iter().map(T::from_mal).collect()
+    }
+}
+
+impl<T: IntoMal> IntoMal for Vec<T> {
+    fn into_mal(self) -> MalType {
+        M::List(self.into_iter().map(T::into_mal).collect())
+    }
+}
+
+// Keys must be strings or keywords, keywords keep their colon, ":key"
+impl<T: FromMal> FromMal for HashMap<String, T> {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        match value {
+            M::Map(map) => map
+                .iter()
+                .map(|(k, v)| match k {
+                    M::Str(k) | M::Key(k) => Ok((k.to_string(), T::from_mal(v)?)),
+                    _ => Err(mismatch(k, "a string or a keyword")),
+                })
+                .collect(),
+            _ => Err(mismatch(value, "a map")),
+        }
+    }
+}
+
+impl<T: IntoMal> IntoMal for HashMap<String, T> {
+    fn into_mal(self) -> MalType {
+        M::Map(
+            self.into_iter()
+                .map(|(k, v)| (M::Str(k.into()), v.into_mal()))
+                .collect(),
+        )
+    }
+}
+
+// Tuples are lists (or vectors) with exactly one element per field
+macro_rules! tuple_conversions {
+    ($len:expr, $($field:ident $idx:tt),*) => {
+        impl<$($field: FromMal),*> FromMal for ($($field,)*) {
+            fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+                match value.if_list()? {
+                    list if list.len() == $len => Ok(($($field::from_mal(&list[$idx])?,)*)),
+                    _ => Err(mismatch(value, concat!("a list of ", $len, " elements"))),
+                }
+            }
+        }
+
+        impl<$($field: IntoMal),*> IntoMal for ($($field,)*) {
+            fn into_mal(self) -> MalType {
+                M::List([$(self.$idx.into_mal()),*].into())
+            }
+        }
+    };
+}
+
+tuple_conversions!(1, A 0);
+tuple_conversions!(2, A 0, B 1);
+tuple_conversions!(3, A 0, B 1, C 2);
+tuple_conversions!(4, A 0, B 1, C 2, D 3);
+
+/// Rust functions whose arguments can all be converted from mal values
+pub trait TypedFn<Args> {
+    const ARITY: usize;
+
+    fn call_typed(&self, name: &str, args: &[MalType]) -> Result<MalType, MalErr>;
+}
+
+// Missing arguments are nil, so that arguments converted to Option can be
+// left out by relaxing the arity with `NativeFn::with_arity`
+fn argument<T: FromMal>(name: &str, args: &[MalType], i: usize) -> Result<T, MalErr> {
+    T::from_mal(args.get(i).unwrap_or_default())
+        .map_err(|e| e.prefixed(format!("{name}: argument {}", i + 1).as_str()))
+}
+
+macro_rules! typed_fn {
+    ($len:expr $(, $arg:ident $idx:tt)*) => {
+        impl<F, R, $($arg: FromMal),*> TypedFn<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> Result<R, MalErr>,
+            R: IntoMal,
+        {
+            const ARITY: usize = $len;
+
+            #[allow(unused_variables)]
+            fn call_typed(&self, name: &str, args: &[MalType]) -> Result<MalType, MalErr> {
+                Ok(self($(argument::<$arg>(name, args, $idx)?),*)?.into_mal())
+            }
+        }
+    };
+}
+
+typed_fn!(0);
+typed_fn!(1, A 0);
+typed_fn!(2, A 0, B 1);
+typed_fn!(3, A 0, B 1, C 2);
+typed_fn!(4, A 0, B 1, C 2, D 3);
+
+impl NativeFn {
+    /// Wrap a Rust function with typed arguments, the number of arguments and
+    /// their types are checked before calling it. Trailing Option arguments
+    /// become optional by lowering the minimum arity with `with_arity`
+    pub fn typed<Args, F: TypedFn<Args> + 'static>(name: &str, help: &str, fun: F) -> Self {
+        let fun_name: MalStr = name.into();
+        Self::new(name, help, move |args| fun.call_typed(&fun_name, args))
+            .with_arity(F::ARITY, Some(F::ARITY))
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests                                                                      //
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{FromMal, IntoMal};
+    use crate::types::{Frac, MalErr, MalType as M, NativeFn};
+    use std::collections::HashMap;
+
+    fn round_trip<T: FromMal + IntoMal + Clone + PartialEq + std::fmt::Debug>(val: T) {
+        assert_eq!(T::from_mal(&val.clone().into_mal()).unwrap(), val);
+    }
+
+    #[test]
+    fn scalars() {
+        round_trip(-3i64);
+        round_trip(u8::MAX);
+        round_trip(i128::MAX);
+        round_trip(0.5f64);
+        round_trip(true);
+        round_trip(false);
+        round_trip('c');
+        round_trip("str".to_string());
+        // Integers must be exact, whole and in range
+        assert!(i32::from_mal(&M::Num(Frac::inexact(1.0))).is_err());
+        assert!(u8::from_mal(&M::Num(Frac::num(256))).is_err());
+        assert!(u8::from_mal(&M::Num(Frac::num(-1))).is_err());
+        assert!(i64::from_mal(&M::Str("1".into())).is_err());
+        // Only nil is false
+        assert!(bool::from_mal(&M::Num(Frac::num(0))).unwrap());
+        assert_eq!(f64::from_mal(&M::Num(Frac::num(1))).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn collections() {
+        round_trip(vec![1, 2, 3]);
+        round_trip(Some(vec!["a".to_string()]));
+        round_trip(None::<i32>);
+        round_trip((1, "a".to_string(), Some(true)));
+        round_trip(HashMap::from([("a".to_string(), vec![1.5])]));
+        assert!(<(i32, i32)>::from_mal(&vec![1, 2, 3].into_mal()).is_err());
+        assert!(Vec::<i32>::from_mal(&vec![M::Nil].into_mal()).is_err());
+    }
+
+    #[test]
+    fn typed() {
+        let add = NativeFn::typed("add", "", |a: i64, b: Option<i64>| -> Result<i64, MalErr> {
+            Ok(a + b.unwrap_or(0))
+        });
+        let args = [1.into_mal(), 2.into_mal()];
+        assert!(matches!(add.call(&args), Ok(M::Num(v)) if v == Frac::num(3)));
+        assert!(matches!(add.call(&[1.into_mal(), M::Nil]), Ok(M::Num(v)) if v == Frac::num(1)));
+        assert!(matches!(
+            add.call(&[1.into_mal()]),
+            Err(e) if e.message() == "add: expected 2 args, got 1"));
+        assert!(matches!(
+            add.call(&[1.into_mal(), "2".into_mal()]),
+            Err(e) if e.message().starts_with("add: argument 2: ")));
+        // Trailing optional arguments can be left out
+        let add = add.with_arity(1, Some(2));
+        assert!(matches!(add.call(&[1.into_mal()]), Ok(M::Num(v)) if v == Frac::num(1)));
+    }
+}
@@ -1,9 +1,9 @@
-use std::io::{self, BufRead, Write};
+use std::io::{self, Write};
 use std::{cell::RefCell, env, rc::Rc};
 
 use crate::env::{
     any_zero, arithmetic_op, car, comparison_op, env_new, env_set, mal_boom, mal_car, mal_cdr,
-    mal_cons, mal_exit, Env,
+    mal_cons, mal_exact, mal_exit, mal_gensym, mal_rationalize, Env,
 };
 
 // This is the first time I implement a macro, and I'm copying it
@@ -35,13 +35,34 @@ macro_rules! env_init {
     };
 }
 
-use crate::parse_tools::read_file;
-use crate::printer::{pr_str, prt};
+use crate::convert::TypedFn;
+use crate::mal_coll::{
+    mal_apply, mal_assoc, mal_concat, mal_conj, mal_contains, mal_count, mal_difference, mal_disj,
+    mal_dissoc, mal_first, mal_get, mal_hash_map, mal_hash_set, mal_intersection, mal_keys,
+    mal_nth, mal_rest, mal_seq, mal_set, mal_subset, mal_union, mal_vals, mal_vector,
+};
+use crate::mal_fs::{
+    delete_file, file_exists, file_info, list_dir, make_dir, path_absolute, path_dir, path_ext,
+    path_join, path_name, rename_file, spit, spit_append,
+};
+use crate::mal_port::{
+    mal_close, mal_get_output_string, mal_open, mal_open_input_string, mal_open_output_string,
+    mal_read_char, mal_read_line, mal_write, Port,
+};
+use crate::mal_process::mal_run;
+use crate::mal_regex::{re_find, re_matches, re_pattern, re_replace, re_seq, re_split};
+use crate::mal_serde::{mal_json_parse, mal_json_stringify};
+use crate::mal_string::{
+    ends_with, index_of, join, lower_case, number_to_string, replace, split, starts_with,
+    string_length, string_to_number, substring, trim, upper_case,
+};
+use crate::parse_tools::{read_file, read_forms};
+use crate::printer::{pr_edn, pr_str, prt};
 use crate::reader::{read_str, Reader};
-use crate::types::{mal_equals, reset_bang, MalErr};
+use crate::types::{mal_equals, reset_bang, MalErr, MalType, NativeFn};
 use crate::types::{
     Frac,
-    MalType::{Atom, Fun, List, Nil, Num, Str},
+    MalType::{Atom, Fun, List, Nil, Str},
 };
 
 macro_rules! if_atom {
@@ -55,11 +76,22 @@ macro_rules! if_atom {
     }};
 }
 
+// Builtin with typed arguments, checked and converted before the call
+fn typed<Args>(name: &str, fun: impl TypedFn<Args> + 'static, help: &str) -> MalType {
+    NativeFn::typed(name, help, fun).into()
+}
+
+// Typed builtin of the first argument, ignoring the others as the untyped
+// builtins it replaced did
+fn unary<A>(name: &str, fun: impl TypedFn<(A,)> + 'static, help: &str) -> MalType {
+    NativeFn::typed(name, help, fun).with_arity(1, None).into()
+}
+
 pub fn ns_init() -> Env {
     env_init!(None,
         // That's it, you are all going to be simpler functions
         "exit"          => Fun(mal_exit, "Quits the program with specified status"),
-        "raise"         => Fun(|a| Err(MalErr::unrecoverable(car(a)?.if_string()?)), "Raise an unrecoverable error with the specified message"),
+        "raise"         => Fun(|a| Err(MalErr::raise(car(a)?.clone())), "Raise an unrecoverable error carrying the specified value, strings are also used as error message"),
         // Ok, keep * and / here because computing basic math operators recursively is fun but not too convenient
         "+"             => Fun(|a| arithmetic_op(0, |a, b| a +  b, a), "Returns the sum of the arguments"),
         "-"             => Fun(|a| arithmetic_op(0, |a, b| a -  b, a), "Returns the difference of the arguments"),
@@ -69,32 +101,109 @@ pub fn ns_init() -> Env {
         ">"             => Fun(|a| comparison_op(   |a, b| a >  b, a), "Returns true if the first argument is strictly greater than the second one, nil otherwise"),
         "<="            => Fun(|a| comparison_op(   |a, b| a <= b, a), "Returns true if the first argument is smaller than or equal to the second one, nil otherwise"),
         ">="            => Fun(|a| comparison_op(   |a, b| a >= b, a), "Returns true if the first argument is greater than or equal to the second one, nil otherwise"),
-        "pr-str"        => Fun(|a| Ok(Str(a.iter().map(|i| pr_str(i, true)).collect::<Vec<String>>().join("").into())), "Print readably all arguments"),
+        "pr-str"        => Fun(|a| Ok(Str(a.iter().map(pr_edn).collect::<Vec<String>>().join("").into())), "Print all arguments as EDN, the output of a single argument can be read back with read-string or read-edn"),
         "str"           => Fun(|a| Ok(Str(a.iter().map(|i| pr_str(i, false)).collect::<Vec<String>>().join("").into())), "Concatenate all arguments as a string"),
         "prn"           => Fun(|a| {a.iter().for_each(|a| print!("{}", pr_str(a, false))); let _ = io::stdout().flush(); Ok(Nil) }, "Print readably all the arguments"),
         "println"       => Fun(|a| {a.iter().for_each(|a| print!("{}", pr_str(a, false))); println!(); Ok(Nil) }, "Print readably all the arguments"),
         "list"          => Fun(|a| Ok(List(a.into())), "Return the arguments as a list"),
         "type"          => Fun(|a| Ok(car(a)?.label_type()), "Returns a label indicating the type of it's argument"),
-        "count"         => Fun(|a| Ok(Num(Frac::num(car(a)?.if_list()?.len() as isize))), "Return the number of elements in the first argument"),
-        "="             => Fun(mal_equals, "Return true if the first two parameters are the same type and content, in case of lists propagate to all elements (NOT IMPLEMENTED for 'Map', 'Fun' and 'MalFun')"),
+        "gensym"        => NativeFn::new("gensym", "Returns a new unique symbol, starting with the string in the first argument if present", mal_gensym).with_arity(0, Some(1)).into(),
+        "count"         => Fun(mal_count, "Return the number of elements in the first argument, entries for maps"),
+        "="             => Fun(mal_equals, "Return true if all the parameters are the same type and content, lists and maps are compared element by element, atoms and functions are only equal to themselves"),
         "car"           => Fun(|a| mal_car(car(a)?), "Returns the first element of the list, NIL if its empty"),
         "cdr"           => Fun(|a| mal_cdr(car(a)?), "Returns all the list but the first element"),
         // Number functions, still to decide how to handle
-        "num"           => Fun(|a| Ok(Num(Frac::num(car(a)?.if_number()?.get_num()))), "Get numerator of the number"),
-        "den"           => Fun(|a| Ok(Num(Frac::num(car(a)?.if_number()?.get_den() as isize))), "Get denominator of the number"),
-        "floor"         => Fun(|a| Ok(Num(Frac::num(car(a)?.if_number()?.int()))), "Approximate the number to the closest smaller integer"),
+        "num"           => unary("num", |x: Frac| Ok(x.numer()), "Get numerator of the number"),
+        "den"           => unary("den", |x: Frac| Ok(x.denom()), "Get denominator of the number"),
+        "floor"         => unary("floor", |x: Frac| Ok(x.trunc()), "Approximate the number to the closest smaller integer"),
+        "exact?"        => unary("exact?", |x: Frac| Ok(x.is_exact()), "Returns true if the number is exact (a fraction), nil if it is inexact (floating point)"),
+        "exact->inexact"=> unary("exact->inexact", |x: Frac| Ok(x.to_inexact()), "Convert the number to the closest floating point value"),
+        "inexact->exact"=> Fun(mal_exact, "Convert the number to the fraction with exactly the same value"),
+        "rationalize"   => Fun(mal_rationalize, "Returns the simplest fraction differing from the first argument at most by the second one,\n; if not specified, floating point values use the range rounding to the same value"),
+        "sqrt"          => unary("sqrt", |x: Frac| Ok(x.sqrt()), "Returns the square root of the number, exact if possible"),
+        "exp"           => unary("exp", |x: f64| Ok(x.exp()), "Returns e raised to the number"),
+        "log"           => unary("log", |x: f64| Ok(x.ln()), "Returns the natural logarithm of the number"),
+        "sin"           => unary("sin", |x: f64| Ok(x.sin()), "Returns the sine of the number (in radians)"),
+        "cos"           => unary("cos", |x: f64| Ok(x.cos()), "Returns the cosine of the number (in radians)"),
+        "tan"           => unary("tan", |x: f64| Ok(x.tan()), "Returns the tangent of the number (in radians)"),
+        "atan"          => unary("atan", |x: f64| Ok(x.atan()), "Returns the arctangent of the number (in radians)"),
         // A tribute to PHP's explode (PHP, a language I never used)
         "boom"          => Fun(mal_boom, "Split a string into a list of characters\n; BE CAREFUL WHEN USING"),
         "read-string"   => Fun(|a| read_str(Reader::new().push(car(a)?.if_string()?)).map_err(MalErr::severe), "Tokenize and read the first argument"),
-        "read-line"     => Fun(|_| Ok(Str(io::stdin().lock().lines().next().unwrap().unwrap().into())), "Read a line from input and return its content"),
-        "slurp"         => Fun(|a| Ok(Str(read_file(car(a)?.if_string()?)?)), "Read a file and return the content as a string"),
+        "string-length" => typed("string-length", string_length, "Returns the number of characters in the string"),
+        "substring"     => NativeFn::typed("substring", "Returns the characters of the string from the second argument up to the third (excluded), or to the end", substring).with_arity(2, Some(3)).into(),
+        "index-of"      => NativeFn::typed("index-of", "Returns the position of the second argument in the string, starting from the third argument if present, NIL if not found", index_of).with_arity(2, Some(3)).into(),
+        "split"         => typed("split", split, "Split the string at every occurrence of the delimiter, an empty delimiter splits every character"),
+        "join"          => NativeFn::typed("join", "Join the elements of the list in a string, using the second argument as separator if present", join).with_arity(1, Some(2)).into(),
+        "trim"          => typed("trim", trim, "Remove leading and trailing whitespace"),
+        "upper-case"    => typed("upper-case", upper_case, "Convert the string to upper case"),
+        "lower-case"    => typed("lower-case", lower_case, "Convert the string to lower case"),
+        "starts-with?"  => typed("starts-with?", starts_with, "Returns true if the string starts with the second argument"),
+        "ends-with?"    => typed("ends-with?", ends_with, "Returns true if the string ends with the second argument"),
+        "replace"       => typed("replace", replace, "Replace every occurrence of the second argument in the string with the third"),
+        "string->number"=> typed("string->number", string_to_number, "Read the number in the string, NIL if it is not a number"),
+        "number->string"=> typed("number->string", number_to_string, "Returns the number printed as a string"),
+        "re-pattern"    => typed("re-pattern", re_pattern, "Compile the string into a regex, that can be passed to the other re- functions in place of the string"),
+        "re-find"       => typed("re-find", re_find, "Returns the first match of the regex in the string, NIL if none\n; the match is a string if the regex has no groups, otherwise a vector of the match and the groups"),
+        "re-matches"    => typed("re-matches", re_matches, "Returns the match of the regex if it spans the whole string, NIL otherwise, see re-find"),
+        "re-seq"        => typed("re-seq", re_seq, "Returns the list of all the matches of the regex in the string, see re-find"),
+        "re-split"      => typed("re-split", re_split, "Split the string at every match of the regex"),
+        "re-replace"    => typed("re-replace", re_replace, "Replace all the matches of the regex in the string with the third argument, where $1 or ${name} are the groups"),
+        "read-edn"      => Fun(|a| read_str(Reader::new().edn().push(car(a)?.if_string()?)).map_err(MalErr::severe), "Read EDN data from the first argument, without evaluating it"),
+        "*stdin*"       => MalType::from(Port::stdin()),
+        "*stdout*"      => MalType::from(Port::stdout()),
+        "open"          => NativeFn::new("open", "Open the file in the first argument as a port, the second argument is the mode: :read (default), :write or :append", mal_open).with_arity(1, Some(2)).into(),
+        "open-input-string" => typed("open-input-string", mal_open_input_string, "Returns an input port reading from the string"),
+        "open-output-string" => typed("open-output-string", mal_open_output_string, "Returns an output port collecting what is written, see get-output-string"),
+        "get-output-string" => NativeFn::new("get-output-string", "Returns what was written to the string port", mal_get_output_string).with_arity(1, Some(1)).into(),
+        "read-line"     => NativeFn::new("read-line", "Read a line from the port, or stdin, without its terminator, returns NIL at the end of the input", mal_read_line).with_arity(0, Some(1)).into(),
+        "read-char"     => NativeFn::new("read-char", "Read a character from the port, or stdin, returns NIL at the end of the input", mal_read_char).with_arity(0, Some(1)).into(),
+        "write"         => NativeFn::new("write", "Write the rest of the arguments to the port, formatted as str does", mal_write).with_arity(1, None).into(),
+        "close"         => NativeFn::new("close", "Flush and close the port, standard streams are left open", mal_close).with_arity(1, Some(1)).into(),
+        "read-file"     => unary("read-file", |f: String| read_forms(&f).map_err(MalErr::severe), "Read all the forms in a file, returns a do form evaluating them"),
+        "slurp"         => unary("slurp", |f: String| read_file(&f), "Read a file and return the content as a string"),
+        "spit"          => typed("spit", spit, "Write the second argument to the file, replacing its content"),
+        "spit-append"   => typed("spit-append", spit_append, "Write the second argument at the end of the file, creating it if needed"),
+        "file-exists?"  => typed("file-exists?", file_exists, "Returns true if the path exists"),
+        "delete-file"   => typed("delete-file", delete_file, "Delete a file or an empty directory"),
+        "rename-file"   => typed("rename-file", rename_file, "Move the file in the first argument to the path in the second"),
+        "make-dir"      => typed("make-dir", make_dir, "Create a directory and its missing parents"),
+        "list-dir"      => typed("list-dir", list_dir, "Returns the sorted list of the names in the directory"),
+        "file-info"     => typed("file-info", file_info, "Returns a map with the :size, :mtime (seconds since epoch) and :dir? of the path"),
+        "path-join"     => Fun(path_join, "Join the arguments into a single path"),
+        "path-dir"      => typed("path-dir", path_dir, "Returns the directory containing the path, \".\" for a bare name, NIL if there is none"),
+        "path-name"     => typed("path-name", path_name, "Returns the last component of the path, NIL if there is none"),
+        "path-ext"      => typed("path-ext", path_ext, "Returns the extension of the path without the dot, NIL if there is none"),
+        "path-absolute" => typed("path-absolute", path_absolute, "Returns the canonical absolute form of an existing path"),
+        "json-parse"    => Fun(mal_json_parse, "Parse a JSON string, objects become maps, arrays vectors, null and false nil"),
+        "json-stringify"=> Fun(mal_json_stringify, "Write the first argument as JSON, indented by the number of spaces in the second argument if present, at most 10"),
         "atom"          => Fun(|a| Ok(Atom(Rc::new(RefCell::new(car(a).unwrap_or_default().clone())))), "Return an atom pointing to the given arg"),
         "deref"         => Fun(|a| if_atom!(car(a)?), "Return the content of the atom argumet"),
         "reset!"        => Fun(reset_bang, "Change the value of the Atom (frist argument) to the second argument"),
         "cons"          => Fun(mal_cons, "Push to front if second element is a list"),
-        "env"           => Fun(|a| match env::var(car(a)?.if_string()?) {
-            Ok(s) => Ok(Str(s.into())),
-            _ => Ok(Nil),
-        }, "Retrieve the specified environment variable, returns NIL if that variable does not exist")
+        "hash-map"      => Fun(mal_hash_map, "Return a map with the arguments as alternated keys and values"),
+        "assoc"         => NativeFn::new("assoc", "Return the map with the rest of the arguments added as alternated keys and values", mal_assoc).with_arity(1, None).into(),
+        "dissoc"        => NativeFn::new("dissoc", "Return the map without the keys in the rest of the arguments", mal_dissoc).with_arity(1, None).into(),
+        "get"           => NativeFn::new("get", "Return the value of the key in the map, or the element at the index in a list or vector,\n; the third argument (default NIL) if not found", mal_get).with_arity(2, Some(3)).into(),
+        "contains?"     => NativeFn::new("contains?", "Return true if the map has the key, the set has the element, or the index is in range for a list or vector", mal_contains).with_arity(2, Some(2)).into(),
+        "keys"          => NativeFn::new("keys", "Return the list of the keys of the map", mal_keys).with_arity(1, Some(1)).into(),
+        "vals"          => NativeFn::new("vals", "Return the list of the values of the map, in the same order as keys", mal_vals).with_arity(1, Some(1)).into(),
+        "vector"        => Fun(mal_vector, "Return the arguments as a vector"),
+        "conj"          => NativeFn::new("conj", "Add the rest of the arguments to the collection: in front of lists, at the end of vectors,\n; maps take [key value] vectors or other maps", mal_conj).with_arity(1, None).into(),
+        "nth"           => NativeFn::new("nth", "Return the element at the index, fails if out of range unless a third argument is given as default", mal_nth).with_arity(2, Some(3)).into(),
+        "first"         => NativeFn::new("first", "Return the first element of the collection, NIL if empty", mal_first).with_arity(1, Some(1)).into(),
+        "rest"          => NativeFn::new("rest", "Return all the elements of the collection but the first as a list", mal_rest).with_arity(1, Some(1)).into(),
+        "seq"           => NativeFn::new("seq", "Return the elements of the collection as a list, NIL if empty\n; maps have [key value] vectors, strings their characters, maps and sets are in no particular order", mal_seq).with_arity(1, Some(1)).into(),
+        "apply"         => NativeFn::new("apply", "Call the function with the arguments in between followed by the elements of the last one", mal_apply).with_arity(1, None).into(),
+        "concat"        => Fun(mal_concat, "Concatenate the elements of all the arguments in a list"),
+        "hash-set"      => Fun(mal_hash_set, "Return a set of the arguments"),
+        "set"           => NativeFn::new("set", "Return a set of the elements of the collection", mal_set).with_arity(1, Some(1)).into(),
+        "disj"          => NativeFn::new("disj", "Return the set without the rest of the arguments", mal_disj).with_arity(1, None).into(),
+        "union"         => Fun(mal_union, "Return the set of the elements in any of the arguments"),
+        "intersection"  => NativeFn::new("intersection", "Return the set of the elements of the first argument that are in all the others", mal_intersection).with_arity(1, None).into(),
+        "difference"    => NativeFn::new("difference", "Return the set of the elements of the first argument that are in none of the others", mal_difference).with_arity(1, None).into(),
+        "subset?"       => NativeFn::new("subset?", "Return true if all the elements of the first set are in the second", mal_subset).with_arity(2, Some(2)).into(),
+        "run"           => NativeFn::new("run", "Run the program in the first argument with the arguments in the second, without a shell\n; options {:in <stdin> :dir <working directory> :env {<name> <value>}}\n; returns {:exit <status> :out <stdout> :err <stderr>}", mal_run).with_arity(1, Some(3)).into(),
+        "env"           => unary("env", |var: String| Ok(env::var(var).ok()), "Retrieve the specified environment variable, returns NIL if that variable does not exist")
     )
 }
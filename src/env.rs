@@ -1,12 +1,14 @@
 use crate::eval::eval;
+use crate::mal_coll::as_index;
 use crate::types::MalErr;
-use crate::types::{Frac, MalMap, MalRet, MalType};
+use crate::types::{Frac, MalArgs, MalRet, MalStr, MalType};
 use std::cell::RefCell;
+use std::collections::HashMap;
 use std::rc::Rc;
 
 #[derive(Clone)]
 pub struct EnvType {
-    data: RefCell<MalMap>,
+    data: RefCell<HashMap<MalStr, MalType>>,
     pub outer: Option<Env>,
 }
 
@@ -28,7 +30,7 @@ pub type Env = Rc<EnvType>;
 
 pub fn env_new(outer: Option<Env>) -> Env {
     Env::new(EnvType {
-        data: RefCell::new(MalMap::new()),
+        data: RefCell::new(HashMap::new()),
         outer,
     })
 }
@@ -54,23 +56,114 @@ pub fn env_get(env: &Env, sym: &str) -> MalRet {
     // Recursive was prettier, but we hate recursion
 }
 
-pub fn env_binds(outer: Env, binds: &MalType, exprs: &[MalType]) -> Result<Env, MalErr> {
-    let env = env_new(Some(outer));
+/// Split the binding targets in the positional ones, returned as their
+/// number, and the one collecting the remaining values, specified after "&"
+pub fn split_rest(binds: &MalArgs) -> Result<(usize, Option<&MalType>), MalErr> {
+    match binds
+        .iter()
+        .position(|x| matches!(x, M::Sym(s) if s.as_ref() == "&"))
+    {
+        None => Ok((binds.len(), None)),
+        Some(i) if i + 2 == binds.len() => Ok((i, binds.last())),
+        Some(_) => Err(MalErr::unrecoverable(
+            "\"&\" must be followed by exactly one binding",
+        )),
+    }
+}
+
+/// Bind the value to the target in the environment, the target can be:
+/// - a symbol, the value is assigned to it
+/// - a vector of targets, bound to the elements of the value in order, the
+///   target following "&" is bound to the list of the remaining elements
+/// - a map, the symbols in the vector under :keys are bound to the values of
+///   the keywords with the same name, the symbol under :as to the whole map
+pub fn env_bind(env: &Env, target: &MalType, val: &MalType) -> Result<(), MalErr> {
+    match target {
+        M::Sym(sym) => env_set(env, sym, val),
+        M::Vector(binds) | M::List(binds) => {
+            let vals = match val {
+                M::Nil => MalArgs::default(),
+                _ => val.if_list()?.clone(),
+            };
+            let (binl, rest) = split_rest(binds)?;
+            let mut iter = vals.iter();
+            for bind in binds.iter().take(binl) {
+                env_bind(env, bind, iter.next().unwrap_or_default())?;
+            }
+            if let Some(rest) = rest {
+                env_bind(env, rest, &M::List(vals.skip(binl)))?;
+            }
+        }
+        M::Map(binds) => {
+            let vals = match val {
+                M::Map(map) => Some(map),
+                M::Nil => None,
+                _ => {
+                    return Err(MalErr::unrecoverable(
+                        format!("{:?} is not a map", prt(val)).as_str(),
+                    ))
+                }
+            };
+            if let Some(keys) = binds.get(&M::Key(":keys".into())) {
+                for key in keys.if_list()? {
+                    let key = key.if_symbol()?;
+                    let found = vals.and_then(|m| m.get(&M::Key(format!(":{key}").into())));
+                    env_set(env, key, found.unwrap_or_default());
+                }
+            }
+            if let Some(whole) = binds.get(&M::Key(":as".into())) {
+                env_set(env, whole.if_symbol()?, val);
+            }
+        }
+        _ => {
+            return Err(MalErr::unrecoverable(
+                format!("{:?} is not a valid binding", prt(target)).as_str(),
+            ))
+        }
+    }
+    Ok(())
+}
+
+/// Returns true if "*STRICT-ARITY*" is set: missing arguments are an error
+/// instead of being set to nil, resolved once when the function is created
+pub fn strict_arity(env: &Env) -> bool {
+    !matches!(env_get(env, "*STRICT-ARITY*"), Err(_) | Ok(M::Nil))
+}
+
+pub fn env_binds(
+    outer: Env,
+    name: &str,
+    binds: &MalType,
+    exprs: &[MalType],
+    strict: bool,
+) -> Result<Env, MalErr> {
+    let env = env_new(Some(outer.clone()));
     let binds = binds.if_list()?;
-    let binl = binds.len();
+    let (binl, rest) = split_rest(binds)?;
     let expl = exprs.len();
-    if binl < expl {
+    if binl < expl && rest.is_none() {
         return Err(MalErr::unrecoverable(
-            format!("Expected {binl} args, got {expl}").as_str(),
+            format!("{name}: expected {binl} args, got {expl}").as_str(),
         ));
     }
-    for (bind, expr) in binds.iter().zip(exprs.iter()) {
-        let bind = bind.if_symbol()?;
-        env_set(&env, bind, expr);
+    if binl > expl && strict {
+        return Err(MalErr::unrecoverable(
+            format!(
+                "{name}: expected {}{binl} args, got {expl}",
+                if rest.is_some() { "at least " } else { "" }
+            )
+            .as_str(),
+        ));
+    }
+    for (bind, expr) in binds.iter().take(binl).zip(exprs.iter()) {
+        env_bind(&env, bind, expr)?;
     }
     // All arguments are optional, if an argument is not specified, set it to nil
     for bind in binds.iter().take(binl).skip(expl) {
-        env_set(&env, bind.if_symbol()?, &M::Nil);
+        env_bind(&env, bind, &M::Nil)?;
+    }
+    if let Some(rest) = rest {
+        env_bind(&env, rest, &M::List(exprs[binl.min(expl)..].into()))?;
     }
     Ok(env)
 }
@@ -93,25 +186,27 @@ pub type CallRet = Result<CallFunc, MalErr>;
 pub fn call_func(func: &MalType, args: &[MalType]) -> CallRet {
     match func {
         M::Fun(func, _) => Ok(CallFunc::Builtin(func(args)?)),
+        M::Native(func) => Ok(CallFunc::Builtin(func.call(args)?)),
         M::MalFun {
             // eval,
             params,
             ast,
             env,
+            name,
+            strict,
             ..
         } => {
-            let inner_env = env_binds(env.clone(), params, args)?;
+            let name = name.as_deref().unwrap_or("λ");
+            let inner_env = env_binds(env.clone(), name, params, args, *strict)?;
             // It's fine to clone the environment here
             // since this is when the function is actually called
             match ast.as_ref() {
                 M::List(list) => {
-                    for x in &list[0..list.len() - 1] {
+                    let (first, last) = first_last(list);
+                    for x in first {
                         eval(x, inner_env.clone())?;
                     }
-                    Ok(CallFunc::MalFun(
-                        list.last().unwrap_or_default().clone(),
-                        inner_env,
-                    ))
+                    Ok(CallFunc::MalFun(last?.clone(), inner_env))
                 }
                 _ => scream!(),
             }
@@ -120,25 +215,30 @@ pub fn call_func(func: &MalType, args: &[MalType]) -> CallRet {
             if args.is_empty() {
                 return Err(MalErr::unrecoverable("No key provided to Map construct"));
             }
-            match &args[0] {
-                M::Str(s) | M::Key(s) => {
-                    Ok(CallFunc::Builtin(m.get(s).unwrap_or_default().clone()))
-                }
-                _ => Err(MalErr::unrecoverable("Map argument must be string or key")),
+            Ok(CallFunc::Builtin(
+                m.get(&args[0]).unwrap_or_default().clone(),
+            ))
+        }
+        // Sets return the value if it is a member, nil otherwise
+        M::Set(s) => {
+            if args.is_empty() {
+                return Err(MalErr::unrecoverable("No value provided to Set construct"));
             }
+            Ok(CallFunc::Builtin(
+                s.get(&args[0]).unwrap_or_default().clone(),
+            ))
         }
         M::Vector(v) | M::List(v) => {
             if args.is_empty() {
                 return Err(MalErr::unrecoverable("No key provided to Vector construct"));
             }
             match &args[0] {
-                M::Num(i) => {
-                    if { 0..v.len() as isize }.contains(&i.int()) {
-                        Ok(CallFunc::Builtin(v[i.int() as usize].clone()))
-                    } else {
-                        Ok(CallFunc::Builtin(M::Nil))
-                    }
-                }
+                M::Num(_) => Ok(CallFunc::Builtin(
+                    as_index(&args[0])
+                        .and_then(|i| v.get(i))
+                        .unwrap_or_default()
+                        .clone(),
+                )),
                 _ => Err(MalErr::unrecoverable("Map argument must be string or key")),
             }
         }
@@ -151,7 +251,7 @@ pub fn call_func(func: &MalType, args: &[MalType]) -> CallRet {
 pub fn any_zero(list: &[MalType]) -> Result<&[MalType], MalErr> {
     match list.len() {
         1 => {
-            if list[0].if_number()?.get_num() == 0 {
+            if list[0].if_number()?.exact_zero() {
                 Err(MalErr::unrecoverable("Attempting division by 0"))
             } else {
                 Ok(list)
@@ -179,23 +279,37 @@ pub fn arithmetic_op(set: isize, f: fn(Frac, Frac) -> Frac, args: &[MalType]) ->
             let mut left = args[0].if_number()?;
             for el in &args[1..] {
                 left = f(left, el.if_number()?);
-
-                const SIM_TRIG: usize = usize::isqrt(isize::MAX as usize);
-
-                // TODO: consider if simplifying at every operation or every N
-                //          or just at the end, no idea on how it scale
-                if std::cmp::max(left.get_num().unsigned_abs(), left.get_den()) > SIM_TRIG {
-                    left = left.simplify();
-                }
             }
-            // Always simplify at the end
-            left.simplify()
+            left
         }
     }))
 }
 
+/// Simplest fraction within the tolerance in the second argument from the first
+pub fn mal_rationalize(args: &[MalType]) -> MalRet {
+    let tolerance = match args.get(1) {
+        Some(tol) => Some(tol.if_number()?),
+        None => None,
+    };
+    match car(args)?.if_number()?.rationalize(tolerance.as_ref()) {
+        Some(val) => Ok(M::Num(val)),
+        None => Err(MalErr::unrecoverable(
+            "Cannot rationalize infinite or NaN numbers",
+        )),
+    }
+}
+
+pub fn mal_exact(args: &[MalType]) -> MalRet {
+    match car(args)?.if_number()?.to_exact() {
+        Some(val) => Ok(M::Num(val)),
+        None => Err(MalErr::unrecoverable(
+            "Cannot convert infinite or NaN numbers to exact",
+        )),
+    }
+}
+
 use MalType::{Nil, T};
-pub fn comparison_op(f: fn(Frac, Frac) -> bool, args: &[MalType]) -> MalRet {
+pub fn comparison_op(f: fn(&Frac, &Frac) -> bool, args: &[MalType]) -> MalRet {
     if args.is_empty() {
         return Ok(Nil);
     }
@@ -203,7 +317,7 @@ pub fn comparison_op(f: fn(Frac, Frac) -> bool, args: &[MalType]) -> MalRet {
     let mut left = left.if_number()?;
     for right in rights {
         let right = right.if_number()?;
-        if !f(left, right) {
+        if !f(&left, &right) {
             return Ok(Nil);
         }
         left = right;
@@ -227,17 +341,11 @@ pub fn cdr(list: &[MalType]) -> &[MalType] {
 }
 
 pub fn mal_cdr(arg: &MalType) -> MalRet {
-    let list = arg.if_list()?;
-    Ok(MalType::List(cdr(list).into()))
+    Ok(MalType::List(arg.if_list()?.rest()))
 }
 
 pub fn mal_car(arg: &MalType) -> MalRet {
-    let list = arg.if_list()?;
-    if list.is_empty() {
-        Ok(Nil)
-    } else {
-        Ok(list[0].clone())
-    }
+    Ok(arg.if_list()?.first().unwrap_or_default().clone())
 }
 
 /// Extract the car and cdr from a list
@@ -245,46 +353,27 @@ pub fn car_cdr(list: &[MalType]) -> Result<(&MalType, &[MalType]), MalErr> {
     Ok((car(list)?, cdr(list)))
 }
 
-// TODO: fix these chonky functions
-
+// Lists share their tail with the new one, no element is copied
 pub fn mal_cons(args: &[MalType]) -> MalRet {
     match args.len() {
-        2 => {
-            let mut car = vec![args[0].clone()];
-            let cdr = args[1].if_list()?;
-            car.extend_from_slice(cdr);
-            Ok(M::List(car.into()))
-        }
+        2 => Ok(M::List(args[1].if_list()?.cons(args[0].clone()))),
         _ => Err(MalErr::unrecoverable("cons: requires 2 arguments")),
     }
 }
 
-fn first(list: &[MalType]) -> &[MalType] {
-    if list.len() > 1 {
-        &list[..list.len() - 1]
-    } else {
-        &list[0..0]
-    }
+/// All the items but the last one, and the last one, nil if the list is empty
+pub fn first_last(list: &MalArgs) -> (impl Iterator<Item = &MalType>, Result<&MalType, MalErr>) {
+    (
+        list.iter().take(list.len().saturating_sub(1)),
+        Ok(list.last().unwrap_or_default()),
+    )
 }
 
-// FIXME: Treat as result for now, change later
-fn last(list: &[MalType]) -> Result<&MalType, MalErr> {
-    match list.len() {
-        0 => Ok(&MalType::Nil),
-        _ => Ok(&list[list.len() - 1]),
-    }
-}
-
-pub fn first_last(list: &[MalType]) -> (&[MalType], Result<&MalType, MalErr>) {
-    (first(list), last(list))
-}
-
-use std::process::exit;
-
+// Quitting is left to whoever runs the interpreter
 pub fn mal_exit(list: &[MalType]) -> MalRet {
     match car(list)? {
-        MalType::Num(val) => exit(val.int() as i32),
-        _ => exit(-1),
+        MalType::Num(val) => Err(MalErr::exit(val.int() as i32)),
+        _ => Err(MalErr::exit(-1)),
     }
 }
 
@@ -293,3 +382,16 @@ pub fn mal_boom(args: &[MalType]) -> MalRet {
     let string = car(args)?.if_string()?;
     Ok(M::List(string.chars().map(M::Ch).collect()))
 }
+
+/// Returns a new symbol at every call, used by macros to bind values without
+/// capturing the symbols of the caller
+pub fn mal_gensym(args: &[MalType]) -> MalRet {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let prefix = match args.first() {
+        Some(prefix) => prefix.if_string()?,
+        None => "G__",
+    };
+    let id = COUNTER.fetch_add(1, Ordering::Relaxed);
+    Ok(M::Sym(format!("{prefix}{id}").into()))
+}
@@ -1,9 +1,9 @@
-use crate::env::{self, call_func, car, car_cdr, CallFunc, CallRet};
-use crate::env::{env_get, env_new, env_set};
-use crate::env::{first_last, Env};
+use crate::env::{call_func, car_cdr, CallFunc, CallRet};
+use crate::env::{env_bind, env_get, env_new, env_set};
+use crate::env::{first_last, split_rest, strict_arity, Env};
 use crate::printer::prt;
 use crate::types::MalType as M;
-use crate::types::{MalArgs, MalErr, MalMap, MalRet, MalType};
+use crate::types::{Frame, MalArgs, MalErr, MalMap, MalRet, MalSet, MalStr, MalType};
 use std::borrow::Borrow;
 use std::rc::Rc;
 
@@ -24,12 +24,22 @@ forms!(NAME_DEF     : "def!",
        NAME_FIND    : "find",
        NAME_QUOTE   : "quote",
        NAME_OK      : "ok?",
-       NAME_EVAL    : "eval");
+       NAME_EVAL    : "eval",
+       NAME_DEFMACRO: "defmacro!",
+       NAME_MEXP    : "macroexpand",
+       NAME_MEXP_1  : "macroexpand-1",
+       NAME_QUASI   : "quasiquote",
+       NAME_UNQUOTE : "unquote",
+       NAME_SPLICE  : "splice-unquote",
+       NAME_TRY     : "try*",
+       NAME_CATCH   : "catch*",
+       NAME_FINALLY : "finally",
+       NAME_LOOP    : "loop",
+       NAME_RECUR   : "recur");
 
 /// Resolve the first element of the list as the function name and call it
 /// with the other elements as arguments
-fn eval_func(list: &MalType) -> CallRet {
-    let list = list.if_list()?;
+fn eval_func(list: &[MalType]) -> CallRet {
     let (func, args) = car_cdr(list)?;
     call_func(func, args)
 }
@@ -55,54 +65,61 @@ macro_rules! inner_do {
 }
 
 /// def! special form:
-///     Evaluate the second expression and assign it to the first symbol
-fn def_bang_form(list: &[MalType], env: Env) -> MalRet {
+///     Evaluate the second expression and assign it to the first symbol, or
+///     destructure it according to the first argument
+fn def_bang_form(list: &MalArgs, env: Env) -> MalRet {
     if list.len() != 2 {
         return Err(MalErr::unrecoverable("def! form: needs 2 arguments"));
     }
-    let (car, _) = car_cdr(list)?;
-    let sym = car.if_symbol()?;
-    let val = &eval(&list[1], env.clone())?;
-    env_set(&env, sym, val);
-    Ok(val.clone())
+    bind_form(&list[0], &list[1], env)
+}
+
+/// Evaluate the expression and bind it to the target, as def! does
+fn bind_form(target: &MalType, expr: &MalType, env: Env) -> MalRet {
+    let val = match (target, eval(expr, env.clone())?) {
+        (M::Sym(sym), val) => val.with_name(sym),
+        (_, val) => val,
+    };
+    env_bind(&env, target, &val)?;
+    Ok(val)
 }
 
 /// let* special form:
 ///     Create a temporary inner environment, assigning pair of elements in
 ///     the first list and returning the evaluation of the second expression
-fn let_star_form(list: &[MalType], env: Env) -> Result<(MalType, Env), MalErr> {
+fn let_star_form(list: &MalArgs, env: Env) -> Result<(MalType, Env), MalErr> {
     // Create the inner environment
     let inner_env = env_new(Some(env.clone()));
     // change the inner environment
-    let (car, cdr) = car_cdr(list)?;
+    let (car, cdr) = list.car_cdr()?;
     let list = car.if_list()?;
     if list.len() % 2 != 0 {
         return Err(MalErr::unrecoverable(
             "let* form, number of arguments must be even",
         ));
     }
-    // TO-DO: Find a way to avoid index looping that is ugly
-    for i in (0..list.len()).step_by(2) {
-        def_bang_form(&list[i..=i + 1], inner_env.clone())?;
+    let mut pairs = list.iter();
+    while let (Some(target), Some(expr)) = (pairs.next(), pairs.next()) {
+        bind_form(target, expr, inner_env.clone())?;
     }
 
-    Ok((inner_do!(cdr, inner_env)?, inner_env))
+    Ok((inner_do!(&cdr, inner_env)?, inner_env))
 }
 
 /// do special form:
 ///     Evaluate all the elements in a list using eval_ast and return the
 ///     result of the last evaluation
-fn do_form(list: &[MalType], env: Env) -> MalRet {
+fn do_form(list: &MalArgs, env: Env) -> MalRet {
     inner_do!(list, env)
 }
 
-fn if_form(list: &[MalType], env: Env) -> MalRet {
+fn if_form(list: &MalArgs, env: Env) -> MalRet {
     if !(2..=3).contains(&list.len()) {
         return Err(MalErr::unrecoverable(
             "if form: number of arguments is wrong",
         ));
     }
-    let (cond, branches) = car_cdr(list)?;
+    let (cond, branches) = list.car_cdr()?;
     Ok(match eval(cond, env.clone())? {
         M::Nil => match branches.len() {
             1 => M::Nil,
@@ -112,38 +129,370 @@ fn if_form(list: &[MalType], env: Env) -> MalRet {
     })
 }
 
-fn fn_star_form(list: &[MalType], env: Env) -> MalRet {
-    let (binds, exprs) = car_cdr(list)?;
-    binds.if_list()?;
+fn fn_star_form(list: &MalArgs, env: Env) -> MalRet {
+    let (binds, exprs) = list.car_cdr()?;
+    split_rest(binds.if_list()?)?;
     Ok(M::MalFun {
         // eval: eval_ast,
         params: Rc::new(binds.clone()),
-        ast: Rc::new(M::List(exprs.into())),
+        ast: Rc::new(M::List(exprs)),
+        strict: strict_arity(&env),
         env,
+        is_macro: false,
+        name: None,
     })
 }
 
+/// defmacro! special form:
+///     Evaluate the second expression, that must result in a function, and
+///     assign it to the first symbol marking it as a macro
+fn defmacro_form(list: &MalArgs, env: Env) -> MalRet {
+    if list.len() != 2 {
+        return Err(MalErr::unrecoverable("defmacro! form: needs 2 arguments"));
+    }
+    let car = list.car()?;
+    let sym = car.if_symbol()?;
+    match eval(&list[1], env.clone())? {
+        M::MalFun {
+            params,
+            ast,
+            env: fun_env,
+            name,
+            strict,
+            ..
+        } => {
+            let val = M::MalFun {
+                params,
+                ast,
+                env: fun_env,
+                is_macro: true,
+                name: name.or_else(|| Some(sym.into())),
+                strict,
+            };
+            env_set(&env, sym, &val);
+            Ok(val)
+        }
+        val => Err(MalErr::unrecoverable(
+            format!("defmacro! form: {:?} is not a function", prt(&val)).as_str(),
+        )),
+    }
+}
+
+/// try* special form:
+///     Evaluate the first expression, if it fails and a catch* clause is
+///     specified, bind the error value to the symbol of the clause and
+///     evaluate its body. The body of the finally clause is always evaluated
+///     afterwards, discarding the results
+fn try_star_form(list: &MalArgs, env: Env) -> MalRet {
+    let (expr, clauses) = list.car_cdr()?;
+    let mut catch = None;
+    let mut finally = None;
+    for clause in &clauses {
+        let clause = clause.if_list()?;
+        if is_form(clause, NAME_CATCH) {
+            let cdr = clause.rest();
+            let (bind, body) = cdr.car_cdr()?;
+            catch = Some((MalStr::from(bind.if_symbol()?), body));
+        } else if is_form(clause, NAME_FINALLY) {
+            finally = Some(clause.rest());
+        } else {
+            return Err(MalErr::unrecoverable(
+                format!(
+                    "try* form: {:?} is not a catch* or finally clause",
+                    prt(&M::List(clause.clone()))
+                )
+                .as_str(),
+            ));
+        }
+    }
+    let ret = match (eval(expr, env.clone()), catch) {
+        (Err(err), Some((bind, body))) if err.exit_code().is_none() => {
+            let inner_env = env_new(Some(env.clone()));
+            env_set(&inner_env, &bind, &err.value());
+            // Evaluate in a closure so that failures still reach finally
+            (|| {
+                let last = inner_do!(&body, inner_env)?;
+                eval(&last, inner_env.clone())
+            })()
+        }
+        (ret, _) => ret,
+    };
+    if let Some(finally) = finally {
+        for ast in &finally {
+            eval(ast, env.clone())?;
+        }
+    }
+    ret
+}
+
+/// Bindings and body of the innermost loop being evaluated, recur binds the
+/// values in a new child of env and jumps back to body
+struct LoopCtx {
+    binds: Vec<MalType>,
+    body: MalType,
+    env: Env,
+}
+
+/// loop special form:
+///     Create a temporary inner environment, assigning pairs of elements in
+///     the first list as in let*, the body can be repeated using recur
+fn loop_form(list: &MalArgs, env: Env) -> Result<(LoopCtx, Env), MalErr> {
+    let (car, body) = list.car_cdr()?;
+    let list = car.if_list()?;
+    if !list.len().is_multiple_of(2) {
+        return Err(MalErr::unrecoverable(
+            "loop form, number of arguments must be even",
+        ));
+    }
+    let body = expand_items(&body, &env, |i| i + 1 == body.len())?;
+    let inner_env = env_new(Some(env.clone()));
+    let mut binds = Vec::new();
+    let mut pairs = list.iter();
+    while let (Some(target), Some(expr)) = (pairs.next(), pairs.next()) {
+        bind_form(target, &expand_loop(expr, false, &env)?, inner_env.clone())?;
+        binds.push(target.clone());
+    }
+    let ctx = LoopCtx {
+        binds,
+        body: M::List(body.cons(M::Sym(NAME_DO.into()))),
+        env,
+    };
+    Ok((ctx, inner_env))
+}
+
+/// recur special form:
+///     Evaluate the arguments and bind them to the symbols of the innermost
+///     loop in a new environment, so that closures created by an iteration
+///     keep its values, then return the body of the loop to evaluate
+fn recur_form(list: &MalArgs, env: Env, ctx: &Option<LoopCtx>) -> Result<(MalType, Env), MalErr> {
+    let ctx = match ctx {
+        Some(ctx) => ctx,
+        None => return Err(MalErr::unrecoverable("recur form: used outside of loop")),
+    };
+    if list.len() != ctx.binds.len() {
+        return Err(MalErr::unrecoverable(
+            format!(
+                "recur form: expected {} args, got {}",
+                ctx.binds.len(),
+                list.len()
+            )
+            .as_str(),
+        ));
+    }
+    // Evaluate everything before rebinding, values may depend on each other
+    let vals = eval_collection(list, env)?;
+    let inner_env = env_new(Some(ctx.env.clone()));
+    for (bind, val) in ctx.binds.iter().zip(vals.iter()) {
+        env_bind(&inner_env, bind, val)?;
+    }
+    Ok((ctx.body.clone(), inner_env))
+}
+
+/// Expand the macros in the statement, ensuring that recur only appears in
+/// tail position so that jumping back to the loop never discards pending
+/// evaluations. The loop evaluates the expansion, so each macro runs once
+fn expand_loop(ast: &MalType, tail: bool, env: &Env) -> MalRet {
+    let ast = macroexpand(ast, env)?;
+    let list = match &ast {
+        M::List(list) => list,
+        M::Vector(vec) => return Ok(M::Vector(expand_items(vec, env, |_| false)?)),
+        M::Map(map) => {
+            let mut ret = MalMap::new();
+            for (k, v) in map {
+                ret.insert_mut(expand_loop(k, false, env)?, expand_loop(v, false, env)?);
+            }
+            return Ok(M::Map(ret));
+        }
+        M::Set(set) => {
+            let items = set.iter().map(|x| expand_loop(x, false, env));
+            return Ok(M::Set(items.collect::<Result<_, _>>()?));
+        }
+        _ => return Ok(ast),
+    };
+    let sym = match list.first() {
+        Some(M::Sym(sym)) => sym.as_ref(),
+        _ => return Ok(M::List(expand_items(list, env, |_| false)?)),
+    };
+    let last = list.len() - 1;
+    let expanded = match sym {
+        NAME_RECUR if !tail => {
+            return Err(MalErr::unrecoverable(
+                "recur form: can only be used in tail position",
+            ))
+        }
+        // Condition and branches
+        NAME_IF => expand_items(list, env, |i| tail && i > 1)?,
+        NAME_DO => expand_items(list, env, |i| tail && i == last)?,
+        NAME_LET | NAME_LOOP => {
+            let binds = match list.get(1) {
+                Some(M::List(binds)) => M::List(expand_binds(binds, env)?),
+                Some(M::Vector(binds)) => M::Vector(expand_binds(binds, env)?),
+                _ => return Err(MalErr::unrecoverable("let* form: bindings must be a list")),
+            };
+            let body = list.skip(2);
+            // Inner loops expand their own body
+            let body = match sym {
+                NAME_LET => expand_items(&body, env, |i| tail && i + 2 == last)?,
+                _ => body,
+            };
+            body.cons(binds).cons(list[0].clone())
+        }
+        // New functions and quoted statements are not part of the loop body,
+        // macroexpand forms receive their argument as written
+        NAME_FN | NAME_FN_ALT | NAME_QUOTE | NAME_QUASI | NAME_MEXP | NAME_MEXP_1 => {
+            return Ok(ast.clone())
+        }
+        _ => expand_items(list, env, |_| false)?,
+    };
+    Ok(M::List(expanded.with_loc(list.loc().cloned())))
+}
+
+/// Expand the items of the list, those for which tail returns true are in tail
+/// position
+fn expand_items(
+    list: &MalArgs,
+    env: &Env,
+    tail: impl Fn(usize) -> bool,
+) -> Result<MalArgs, MalErr> {
+    let items = list
+        .iter()
+        .enumerate()
+        .map(|(i, x)| expand_loop(x, tail(i), env));
+    Ok(items
+        .collect::<Result<MalArgs, _>>()?
+        .with_loc(list.loc().cloned()))
+}
+
+/// Expand the values of the bindings, leaving the targets as they are
+fn expand_binds(binds: &MalArgs, env: &Env) -> Result<MalArgs, MalErr> {
+    let items = binds.iter().enumerate().map(|(i, x)| match i % 2 {
+        0 => Ok(x.clone()),
+        _ => expand_loop(x, false, env),
+    });
+    Ok(items
+        .collect::<Result<MalArgs, _>>()?
+        .with_loc(binds.loc().cloned()))
+}
+
+/// Returns true if the list is a call to the specified form
+fn is_form(list: &MalArgs, form: &str) -> bool {
+    matches!(list.first(), Some(M::Sym(sym)) if sym.as_ref() == form)
+}
+
+/// quasiquote special form:
+///     Return the statement without evaluating it, except for the elements
+///     wrapped in unquote, that are evaluated, and splice-unquote, that are
+///     evaluated and spliced in the enclosing list or vector
+fn quasiquote_form(ast: &MalType, env: &Env) -> MalRet {
+    match ast {
+        M::List(list) if is_form(list, NAME_UNQUOTE) => eval(list.rest().car()?, env.clone()),
+        M::List(list) => Ok(M::List(quasiquote_collection(list, env)?)),
+        M::Vector(vec) => Ok(M::Vector(quasiquote_collection(vec, env)?)),
+        _ => Ok(ast.clone()),
+    }
+}
+
+fn quasiquote_collection(list: &MalArgs, env: &Env) -> Result<MalArgs, MalErr> {
+    let mut ret = Vec::new();
+    for el in list {
+        match el {
+            M::List(sub) if is_form(sub, NAME_SPLICE) => {
+                let spliced = eval(sub.rest().car()?, env.clone())?;
+                ret.extend(spliced.if_list()?.iter().cloned())
+            }
+            _ => ret.push(quasiquote_form(el, env)?),
+        }
+    }
+    Ok(ret.into())
+}
+
+/// Return the macro called by the list, if any
+fn macro_call(ast: &MalType, env: &Env) -> Option<MalType> {
+    match ast {
+        M::List(list) => match list.first() {
+            Some(M::Sym(sym)) => match env_get(env, sym) {
+                Ok(mac @ M::MalFun { is_macro: true, .. }) => Some(mac),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Call the function with the provided arguments up to its complete
+/// evaluation
+pub fn apply_fun(func: &MalType, args: &[MalType]) -> MalRet {
+    match call_func(func, args)? {
+        CallFunc::Builtin(ret) => Ok(ret),
+        CallFunc::MalFun(ast, env) => eval(&ast, env),
+    }
+}
+
+/// Expand the macro call once, returns true if an expansion occurred
+fn macroexpand_1(ast: &MalType, env: &Env) -> Result<(MalType, bool), MalErr> {
+    match macro_call(ast, env) {
+        Some(mac) => Ok((apply_fun(&mac, &ast.if_list()?.rest().to_vec())?, true)),
+        None => Ok((ast.clone(), false)),
+    }
+}
+
+/// Expand the macro call until the result is no longer a macro call
+fn macroexpand(ast: &MalType, env: &Env) -> MalRet {
+    let mut ast = ast.clone();
+    loop {
+        let (expanded, done) = macroexpand_1(&ast, env)?;
+        if !done {
+            return Ok(expanded);
+        }
+        ast = expanded;
+    }
+}
+
 use crate::printer::print_malfun;
 
-pub fn help_form(list: &[MalType], env: Env) -> MalRet {
-    let (sym, _) = car_cdr(list)?;
+pub fn help_form(list: &MalArgs, env: Env) -> MalRet {
+    let sym = list.car()?;
     let sym_str = sym.if_symbol()?;
-    match eval(sym, env.clone())? {
-        M::Fun(_, desc) => eprintln!("{sym_str}\t[builtin]: {desc}\n"),
-        M::MalFun { params, ast, .. } => print_malfun(sym_str, params, ast),
-        _ => eprintln!("{}\t[symbol]: {}\n", sym_str, prt(&env_get(&env, sym_str)?)),
-    }
-    Ok(M::Nil)
+    let text = match eval(sym, env.clone())? {
+        M::Fun(_, desc) => format!("{sym_str}\t[builtin]: {desc}"),
+        M::Native(fun) => format!("{sym_str}\t[builtin, {} args]: {}", fun.arity(), fun.help),
+        M::MalFun {
+            params,
+            ast,
+            is_macro,
+            ..
+        } => print_malfun(
+            sym_str,
+            if is_macro { "macro" } else { "function" },
+            params,
+            ast,
+        ),
+        _ => format!("{}\t[symbol]: {}", sym_str, prt(&env_get(&env, sym_str)?)),
+    };
+    Ok(M::Str(text.into()))
 }
 
-pub fn find_form(list: &[MalType], env: Env) -> MalRet {
+pub fn find_form(list: &MalArgs, env: Env) -> MalRet {
     let mut filtered = env.keys();
     for mat in list {
         let mat = mat.if_symbol()?;
         filtered.retain(|x| x.contains(mat) && !x.starts_with('_'));
     }
-    eprintln!("\t[matches]:\n{}\n", filtered.join(" "));
-    Ok(M::Nil)
+    Ok(M::Str(
+        format!("\t[matches]:\n{}", filtered.join(" ")).into(),
+    ))
+}
+
+/// Whether the form asks for help or find, whose text is meant to be shown
+/// as it is rather than printed readably
+pub fn is_help_form(ast: &MalType) -> bool {
+    match ast {
+        M::List(list) => {
+            matches!(list.first(), Some(M::Sym(sym)) if [NAME_HELP, NAME_FIND].contains(&sym.borrow()))
+        }
+        _ => false,
+    }
 }
 
 pub fn outermost(env: &Env) -> Env {
@@ -154,16 +503,43 @@ pub fn outermost(env: &Env) -> Env {
     env.clone()
 }
 
+/// Describe a function call for error traces, named functions use their own
+/// name, anything else the symbol used to call it
+fn call_frame(ast: &MalType, apply_list: &[MalType]) -> Frame {
+    let (head, loc) = match ast {
+        M::List(list) => (list.first(), list.loc().cloned()),
+        _ => (None, None),
+    };
+    let name = match (apply_list.first(), head) {
+        (
+            Some(M::MalFun {
+                name: Some(name), ..
+            }),
+            _,
+        ) => Some(name.clone()),
+        (_, Some(M::Sym(sym))) => Some(sym.clone()),
+        _ => None,
+    };
+    Frame { name, loc }
+}
+
 macro_rules! apply {
-    ($ast:expr, $env:expr) => {{
-        let apply_list = &eval_ast(&$ast, $env.clone())?;
-        let eval_ret = eval_func(apply_list)?;
+    ($head:expr, $args:expr, $ast:expr, $env:expr, $loop_ctx:expr, $frame:expr) => {{
+        let mut apply_list = vec![$head];
+        apply_list.extend(eval_collection($args, $env.clone())?);
+        let apply_list = &apply_list;
+        let frame = call_frame(&$ast, apply_list);
+        let eval_ret = eval_func(apply_list).map_err(|e| e.traced(frame.clone()))?;
 
         match eval_ret {
             CallFunc::Builtin(ret) => return Ok(ret),
             CallFunc::MalFun(fun_ast, fun_env) => {
                 $ast = fun_ast;
                 $env = fun_env;
+                // The body of the function is not part of the loop
+                $loop_ctx = None;
+                // Tail calls take the place of the caller in traces
+                *$frame = Some(frame);
             }
         }
     }};
@@ -172,76 +548,135 @@ macro_rules! apply {
 /// Intermediate function to discern special forms from defined symbols
 pub fn eval(ast: &MalType, env: Env) -> MalRet {
     let mut ast = ast.clone();
+    let mut frame = None;
+    // On failure ast is the form being evaluated, report its position and the
+    // function whose body it belongs to
+    eval_form(&mut ast, env, &mut frame).map_err(|e| {
+        let e = match &ast {
+            M::List(list) => e.at(list.loc()),
+            _ => e,
+        };
+        match frame {
+            Some(frame) => e.traced(frame),
+            None => e,
+        }
+    })
+}
+
+fn eval_form(ast: &mut MalType, env: Env, frame: &mut Option<Frame>) -> MalRet {
     let mut env = env;
+    let mut loop_ctx = None;
     loop {
-        match &ast {
+        match &*ast {
             M::List(list) if list.is_empty() => return Ok(ast.clone()),
             M::List(list) => {
-                let (symbol, args) = car_cdr(list)?;
-                if let M::Sym(sym) = symbol {
-                    match sym.borrow() {
-                        // I don't like to borrow tho
-                        NAME_DEF => return def_bang_form(args, env.clone()), // Set for env
-                        NAME_LET => {(ast, env) = let_star_form(args, env.clone())?; continue;},
-                        NAME_DO  => {ast = do_form(args, env.clone())?; continue;},
-                        NAME_IF  => {ast = if_form(args, env.clone())?; continue;},
-                        NAME_FN | NAME_FN_ALT /* :) */ => {
-                            return fn_star_form(args, env.clone())
-                        }
-                        NAME_HELP => return help_form(args, env.clone()),
-                        NAME_FIND => return find_form(args, env.clone()),
-                        // Oh God, what have I done
-                        NAME_QUOTE => return Ok(car(args)?.clone()),
-                        NAME_OK => {
-                            return match eval(car(args)?, env.clone()) {
-                                Err(_) => Ok(M::Nil),
-                                _ => Ok(M::T),
+                let (symbol, args) = list.car_cdr()?;
+                let head = match symbol {
+                    M::Sym(sym) => {
+                        match sym.borrow() {
+                            // I don't like to borrow tho
+                            NAME_DEF => return def_bang_form(&args, env.clone()), // Set for env
+                            NAME_DEFMACRO => return defmacro_form(&args, env.clone()),
+                            NAME_MEXP => return macroexpand(args.car()?, &env),
+                            NAME_MEXP_1 => return Ok(macroexpand_1(args.car()?, &env)?.0),
+                            NAME_LET => {(*ast, env) = let_star_form(&args, env.clone())?; continue;},
+                            NAME_DO  => {*ast = do_form(&args, env.clone())?; continue;},
+                            NAME_IF  => {*ast = if_form(&args, env.clone())?; continue;},
+                            NAME_LOOP => {
+                                let (ctx, inner_env) = loop_form(&args, env.clone())?;
+                                (*ast, env) = (ctx.body.clone(), inner_env);
+                                loop_ctx = Some(ctx);
+                                continue;
                             }
+                            NAME_RECUR => {
+                                (*ast, env) = recur_form(&args, env.clone(), &loop_ctx)?;
+                                continue;
+                            }
+                            NAME_FN | NAME_FN_ALT /* :) */ => {
+                                return fn_star_form(&args, env.clone())
+                            }
+                            NAME_HELP => return help_form(&args, env.clone()),
+                            NAME_FIND => return find_form(&args, env.clone()),
+                            // Oh God, what have I done
+                            NAME_QUOTE => return Ok(args.car()?.clone()),
+                            NAME_QUASI => return quasiquote_form(args.car()?, &env),
+                            NAME_TRY => return try_star_form(&args, env.clone()),
+                            NAME_UNQUOTE | NAME_SPLICE => {
+                                return Err(MalErr::unrecoverable(
+                                    format!("{sym} form: used outside of quasiquote").as_str(),
+                                ))
+                            }
+                            NAME_OK => {
+                                return match eval(args.car()?, env.clone()) {
+                                    Err(e) if e.exit_code().is_some() => Err(e),
+                                    Err(_) => Ok(M::Nil),
+                                    _ => Ok(M::T),
+                                }
+                            }
+                            // Special form, sad
+                            // Bruh, is basically double eval
+                            NAME_EVAL => {
+                                *ast = eval(args.car()?, env.clone())?;
+                                // Climb to the outermost environment (The repl env)
+                                env = outermost(&env);
+                                loop_ctx = None;
+                                continue;
+                            }
+                            _ => {}
                         }
-                        // Special form, sad
-                        // Bruh, is basically double eval
-                        NAME_EVAL => {
-                            ast = eval(env::car(args)?, env.clone())?;
-                            // Climb to the outermost environment (The repl env)
-                            env = outermost(&env);
-                            continue;
-                        }
-                        _ => {}
+                        env_get(&env, sym)?
                     }
+                    _ => eval(symbol, env.clone())?,
+                };
+                // The expansion of a macro call takes its place, the head is
+                // resolved once for both macros and functions
+                if let M::MalFun { is_macro: true, .. } = head {
+                    *ast = apply_fun(&head, &args.to_vec())?;
+                    continue;
                 }
                 // "apply"/invoke
-                apply!(ast, env)
+                apply!(head, &args, *ast, env, loop_ctx, frame)
             }
-            _ => return eval_ast(&ast, env),
+            _ => return eval_ast(ast, env),
         }
     }
 }
 
 /// Separately evaluate all elements in a collection (list or vector)
-fn eval_collection(list: &MalArgs, env: Env) -> Result<MalArgs, MalErr> {
-    let mut ret = Vec::new();
-    for el in list.as_ref() {
+fn eval_collection(list: &MalArgs, env: Env) -> Result<Vec<MalType>, MalErr> {
+    let mut ret = Vec::with_capacity(list.len());
+    for el in list {
         ret.push(eval(el, env.clone())?);
     }
-    Ok(ret.into())
+    Ok(ret)
 }
 
-/// Evaluate the values of a map
+/// Evaluate the keys and the values of a map
 fn eval_map(map: &MalMap, env: Env) -> MalRet {
     let mut ret = MalMap::new();
     for (k, v) in map {
-        ret.insert(k.clone(), eval(v, env.clone())?);
+        ret.insert_mut(eval(k, env.clone())?, eval(v, env.clone())?);
     }
     Ok(M::Map(ret))
 }
 
+/// Evaluate the elements of a set, equal results are kept once
+fn eval_set(set: &MalSet, env: Env) -> MalRet {
+    let mut ret = MalSet::new();
+    for el in set.iter() {
+        ret.insert_mut(eval(el, env.clone())?);
+    }
+    Ok(M::Set(ret))
+}
+
 /// Eval the provided ast
 fn eval_ast(ast: &MalType, env: Env) -> MalRet {
     match ast {
         M::Sym(sym) => env_get(&env, sym),
-        M::List(list) => Ok(M::List(eval_collection(list, env)?)),
-        M::Vector(vec) => Ok(M::Vector(eval_collection(vec, env)?)),
+        M::List(list) => Ok(M::List(eval_collection(list, env)?.into())),
+        M::Vector(vec) => Ok(M::Vector(eval_collection(vec, env)?.into())),
         M::Map(map) => eval_map(map, env),
+        M::Set(set) => eval_set(set, env),
         _ => Ok(ast.clone()),
     }
 }
@@ -0,0 +1,188 @@
+// Entry point for programs embedding mal, the binary is just a client of this
+
+use crate::convert::{FromMal, IntoMal};
+use crate::core::ns_init;
+use crate::env::{env_get, env_set, Env};
+use crate::eval::eval;
+use crate::parse_tools::{read_forms, read_source};
+use crate::reader::{read_all, Reader};
+use crate::step6_file::rep;
+use crate::types::{MalErr, MalRet, MalType, NativeFn};
+use std::env;
+
+// The core library shipped with the sources, used when none is provided
+const CORE: &str = include_str!("../core/core.mal");
+
+/// A mal environment ready to evaluate code, nothing is ever printed to stderr
+/// and `exit` is returned as an error (see `MalErr::exit_code`)
+pub struct Interpreter {
+    env: Env,
+}
+
+impl Interpreter {
+    /// Builtins and the core library bundled with the crate, an error if the
+    /// core library fails to load
+    pub fn new() -> Result<Self, MalErr> {
+        let mal = Self::bare();
+        eval(&read_source(CORE, "core.mal")?, mal.env.clone())?;
+        Ok(mal)
+    }
+
+    /// Builtins only, `MAL_HOME` is set from the environment variable with
+    /// the same name, or defaults to "~/.config/mal"
+    pub fn bare() -> Self {
+        let home = env::var("MAL_HOME")
+            .unwrap_or_else(|_| env::var("HOME").unwrap_or_default() + "/.config/mal");
+        let mal = Self { env: ns_init() };
+        mal.define("MAL_HOME", MalType::Str(home.into()));
+        mal
+    }
+
+    /// Evaluate all the forms in the input, returns the value of the last one
+    pub fn eval_str(&self, input: &str) -> MalRet {
+        let mut ret = MalType::Nil;
+        for form in read_all(Reader::new().push(input))? {
+            ret = eval(&form, self.env.clone())?;
+        }
+        Ok(ret)
+    }
+
+    /// Evaluate all the forms in the input and convert the value of the last
+    /// one, see `FromMal`
+    pub fn eval_as<T: FromMal>(&self, input: &str) -> Result<T, MalErr> {
+        T::from_mal(&self.eval_str(input)?)
+    }
+
+    /// Evaluate all the forms in a file
+    pub fn load_file(&self, filename: &str) -> MalRet {
+        eval(&read_forms(filename)?, self.env.clone())
+    }
+
+    /// Read, evaluate and print all the forms available in the reader,
+    /// recoverable errors signal that the input is incomplete
+    pub fn rep(&self, reader: &Reader) -> Result<Vec<String>, MalErr> {
+        rep(reader, &self.env)
+    }
+
+    /// Bind a value to a symbol in the global environment
+    pub fn define(&self, name: &str, value: impl IntoMal) {
+        env_set(&self.env, name, &value.into_mal())
+    }
+
+    /// Bind a native function to its name in the global environment
+    pub fn define_fn(&self, fun: NativeFn) {
+        let name = fun.name.clone();
+        self.define(&name, fun)
+    }
+
+    /// Value bound to a symbol in the global environment
+    pub fn get(&self, name: &str) -> Option<MalType> {
+        env_get(&self.env, name).ok()
+    }
+
+    /// Value bound to a symbol in the global environment converted, an error
+    /// if the symbol is not defined or the conversion fails
+    pub fn get_as<T: FromMal>(&self, name: &str) -> Result<T, MalErr> {
+        T::from_mal(&env_get(&self.env, name)?)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests                                                                      //
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::Interpreter;
+    use crate::reader::Reader;
+    use crate::types::{Frac, MalType as M, NativeFn};
+    use std::{cell::Cell, rc::Rc};
+
+    #[test]
+    fn eval_str() {
+        let mal = Interpreter::new().unwrap();
+        // All forms are evaluated, the last one is returned
+        assert!(matches!(
+            mal.eval_str("(def! a 1) (+ a 1)"),
+            Ok(M::Num(v)) if v == Frac::num(2)));
+        assert!(matches!(mal.eval_str("(car 1)"), Err(e) if !e.is_recoverable()));
+        assert!(matches!(mal.eval_str("(car"), Err(e) if e.is_recoverable()));
+    }
+
+    #[test]
+    fn define_get() {
+        let mal = Interpreter::new().unwrap();
+        mal.define("x", 3);
+        assert!(matches!(mal.eval_str("(* x 2)"), Ok(M::Num(v)) if v == Frac::num(6)));
+        mal.eval_str("(def! y \"str\")").unwrap();
+        assert!(matches!(mal.get("y"), Some(M::Str(s)) if s.as_ref() == "str"));
+        assert!(mal.get("undefined").is_none());
+    }
+
+    #[test]
+    fn typed_results() {
+        let mal = Interpreter::new().unwrap();
+        assert_eq!(mal.eval_as::<i64>("(+ 1 2)").unwrap(), 3);
+        assert_eq!(
+            mal.eval_as::<Vec<String>>("[\"a\" \"b\"]").unwrap(),
+            ["a", "b"]
+        );
+        assert_eq!(mal.eval_as::<Option<bool>>("nil").unwrap(), None);
+        assert!(matches!(
+            mal.eval_as::<i64>("1/2"),
+            Err(e) if e.message() == "\"1/2\" is not an integer"));
+        assert!(mal.eval_as::<i64>("(car 1)").is_err());
+        mal.define("pair", (1, "one".to_string()));
+        assert_eq!(
+            mal.get_as::<(i64, String)>("pair").unwrap(),
+            (1, "one".into())
+        );
+        assert!(mal.get_as::<i64>("undefined").is_err());
+        assert!(mal.get_as::<String>("pair").is_err());
+    }
+
+    #[test]
+    fn rep() {
+        let mal = Interpreter::new().unwrap();
+        // Help is shown as it is, other strings are printed readably
+        let help = mal.rep(Reader::new().push("(help +)")).unwrap();
+        assert_eq!(help, ["+\t[builtin]: Returns the sum of the arguments"]);
+        let text = mal.rep(Reader::new().push("\"a\\nb\"")).unwrap();
+        assert_eq!(text, ["\"a\\nb\""]);
+    }
+
+    #[test]
+    fn exit() {
+        let mal = Interpreter::new().unwrap();
+        // exit is reported to the caller and cannot be caught
+        assert!(matches!(
+            mal.eval_str("(try* (exit 3) (catch* e nil))"),
+            Err(e) if e.exit_code() == Some(3)));
+        assert!(matches!(mal.eval_str("(ok? (exit 1))"), Err(e) if e.exit_code() == Some(1)));
+    }
+
+    #[test]
+    fn native_fn() {
+        let mal = Interpreter::new().unwrap();
+        let count = Rc::new(Cell::new(0));
+        let counter = count.clone();
+        mal.define_fn(
+            NativeFn::new("tick", "Add the argument to the counter", move |a| {
+                counter.set(counter.get() + a[0].if_number()?.int());
+                Ok(M::Num(Frac::num(counter.get())))
+            })
+            .with_arity(1, Some(1)),
+        );
+        assert!(matches!(mal.eval_str("(tick 2) (tick 3)"), Ok(M::Num(v)) if v == Frac::num(5)));
+        assert_eq!(count.get(), 5);
+        assert!(matches!(
+            mal.eval_str("(tick)"),
+            Err(e) if e.message() == "tick: expected 1 args, got 0"));
+        assert!(matches!(
+            mal.eval_str("(help tick)"),
+            Ok(M::Str(s)) if s.as_ref() == "tick\t[builtin, 1 args]: Add the argument to the counter"));
+        assert!(matches!(mal.eval_str("(type tick)"), Ok(M::Key(k)) if k.as_ref() == ":lambda"));
+        assert!(matches!(mal.eval_str("(map tick '(1 1))"), Ok(M::List(l)) if l.len() == 2));
+        assert_eq!(count.get(), 7);
+    }
+}
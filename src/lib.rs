@@ -0,0 +1,28 @@
+// Mal interpreter as a library, embed it through `Interpreter`
+
+mod convert;
+mod core;
+mod env;
+mod eval;
+mod interpreter;
+mod mal_coll;
+mod mal_fs;
+mod mal_port;
+mod mal_process;
+mod mal_regex;
+mod mal_serde;
+mod mal_string;
+mod mal_tests;
+mod parse_tools;
+mod printer;
+mod reader;
+mod step6_file;
+mod types;
+
+pub use convert::{FromMal, IntoMal, TypedFn};
+pub use interpreter::Interpreter;
+pub use mal_port::Port;
+pub use mal_serde::{from_value, to_mal};
+pub use printer::{pr_edn, pr_str};
+pub use reader::Reader;
+pub use types::{Frac, Frame, Loc, MalArgs, MalErr, MalRet, MalType, NativeFn, Severity};
@@ -0,0 +1,317 @@
+// Collection builtins, collections are never modified: new ones are returned,
+// sharing their structure with the original
+
+use crate::env::{car, car_cdr};
+use crate::eval::apply_fun;
+use crate::printer::prt;
+use crate::types::{make_map, Frac, MalArgs, MalErr, MalMap, MalRet, MalSet, MalType};
+
+use MalType as M;
+
+fn not_a(name: &str, kind: &str, value: &MalType) -> MalErr {
+    MalErr::unrecoverable(format!("{name}: {:?} is not a {kind}", prt(value)).as_str())
+}
+
+// nil is an empty map
+fn map_arg(name: &str, value: &MalType) -> Result<MalMap, MalErr> {
+    match value {
+        M::Map(map) => Ok(map.clone()),
+        M::Nil => Ok(MalMap::new()),
+        _ => Err(not_a(name, "map", value)),
+    }
+}
+
+/// Elements of the set sorted as they are printed, so that their order is
+/// always the same, formatting each of them
+pub fn set_items(set: &MalSet) -> Vec<&MalType> {
+    let mut items = set.iter().collect::<Vec<_>>();
+    items.sort_by_cached_key(|item| prt(item));
+    items
+}
+
+// nil is an empty set
+fn set_arg(name: &str, value: &MalType) -> Result<MalSet, MalErr> {
+    match value {
+        M::Set(set) => Ok(set.clone()),
+        M::Nil => Ok(MalSet::new()),
+        _ => Err(not_a(name, "set", value)),
+    }
+}
+
+/// Items of the collection in order, maps have [key value] vectors and
+/// strings their characters, nil has none. Maps and sets are not sorted: the
+/// order is unspecified, but always the same for the same map or set
+fn seq_items(name: &str, value: &MalType) -> Result<MalArgs, MalErr> {
+    match value {
+        M::List(list) | M::Vector(list) => Ok(list.clone()),
+        M::Map(map) => Ok(map
+            .iter()
+            .map(|(k, v)| M::Vector([k.clone(), v.clone()].into()))
+            .collect()),
+        M::Set(set) => Ok(set.iter().cloned().collect()),
+        M::Str(s) => Ok(s.chars().map(M::Ch).collect()),
+        M::Nil => Ok(MalArgs::default()),
+        _ => Err(not_a(name, "collection", value)),
+    }
+}
+
+/// Numbers equal to a non negative integer are indexes, 1.0 as well as 1, as
+/// they are the same map key
+pub fn as_index(value: &MalType) -> Option<usize> {
+    match value {
+        M::Num(num) => num
+            .to_exact()
+            .and_then(|num| num.to_i128())
+            .and_then(|index| usize::try_from(index).ok()),
+        _ => None,
+    }
+}
+
+// Value under the key of a map, at the index of a list or vector, or the
+// element of a set
+fn lookup<'a>(coll: &'a MalType, key: &MalType) -> Option<&'a MalType> {
+    match coll {
+        M::Map(map) => map.get(key),
+        M::Set(set) => set.get(key),
+        M::List(list) | M::Vector(list) => as_index(key).and_then(|index| list.get(index)),
+        _ => None,
+    }
+}
+
+pub fn mal_hash_map(args: &[MalType]) -> MalRet {
+    make_map(args.into())
+}
+
+/// (assoc map key value ...)
+pub fn mal_assoc(args: &[MalType]) -> MalRet {
+    let (map, pairs) = car_cdr(args)?;
+    if !pairs.len().is_multiple_of(2) {
+        return Err(MalErr::unrecoverable(
+            "assoc: missing value for the last key",
+        ));
+    }
+    let mut map = map_arg("assoc", map)?;
+    for pair in pairs.chunks(2) {
+        map.insert_mut(pair[0].clone(), pair[1].clone());
+    }
+    Ok(M::Map(map))
+}
+
+/// (dissoc map key ...)
+pub fn mal_dissoc(args: &[MalType]) -> MalRet {
+    let (map, keys) = car_cdr(args)?;
+    let mut map = map_arg("dissoc", map)?;
+    for key in keys {
+        map.remove_mut(key);
+    }
+    Ok(M::Map(map))
+}
+
+/// (get coll key [default])
+pub fn mal_get(args: &[MalType]) -> MalRet {
+    Ok(lookup(&args[0], &args[1])
+        .or(args.get(2))
+        .unwrap_or_default()
+        .clone())
+}
+
+pub fn mal_contains(args: &[MalType]) -> MalRet {
+    Ok(match lookup(&args[0], &args[1]) {
+        Some(_) => M::T,
+        None => M::Nil,
+    })
+}
+
+/// Keys of the map, in the same order as vals
+pub fn mal_keys(args: &[MalType]) -> MalRet {
+    let map = map_arg("keys", &args[0])?;
+    Ok(M::List(map.keys().cloned().collect()))
+}
+
+pub fn mal_vals(args: &[MalType]) -> MalRet {
+    let map = map_arg("vals", &args[0])?;
+    Ok(M::List(map.values().cloned().collect()))
+}
+
+pub fn mal_vector(args: &[MalType]) -> MalRet {
+    Ok(M::Vector(args.into()))
+}
+
+/// (conj coll item ...), items are added where it is cheapest: in front of
+/// lists, at the end of vectors; maps take [key value] vectors or other maps
+pub fn mal_conj(args: &[MalType]) -> MalRet {
+    let (coll, items) = car_cdr(args)?;
+    match coll {
+        M::List(list) => Ok(M::List(
+            items
+                .iter()
+                .fold(list.clone(), |list, item| list.cons(item.clone())),
+        )),
+        M::Nil => Ok(M::List(
+            items
+                .iter()
+                .fold(MalArgs::default(), |list, item| list.cons(item.clone())),
+        )),
+        M::Vector(vec) => {
+            Ok(M::Vector(items.iter().fold(vec.clone(), |vec, item| {
+                vec.push_back(item.clone())
+            })))
+        }
+        M::Map(map) => {
+            let mut map = map.clone();
+            for item in items {
+                match item {
+                    M::Map(other) => other
+                        .iter()
+                        .for_each(|(k, v)| map.insert_mut(k.clone(), v.clone())),
+                    M::Vector(pair) if pair.len() == 2 => {
+                        map.insert_mut(pair[0].clone(), pair[1].clone())
+                    }
+                    _ => return Err(not_a("conj", "map entry", item)),
+                }
+            }
+            Ok(M::Map(map))
+        }
+        M::Set(set) => {
+            let mut set = set.clone();
+            items.iter().for_each(|item| set.insert_mut(item.clone()));
+            Ok(M::Set(set))
+        }
+        _ => Err(not_a("conj", "collection", coll)),
+    }
+}
+
+/// (nth coll index [default]), fails if the index is out of range and there
+/// is no default
+pub fn mal_nth(args: &[MalType]) -> MalRet {
+    let items = seq_items("nth", &args[0])?;
+    let index = match as_index(&args[1]) {
+        Some(index) => index,
+        None => return Err(not_a("nth", "valid index", &args[1])),
+    };
+    match (items.get(index), args.get(2)) {
+        (Some(item), _) | (None, Some(item)) => Ok(item.clone()),
+        (None, None) => Err(MalErr::unrecoverable(
+            format!("nth: index {index} out of range for length {}", items.len()).as_str(),
+        )),
+    }
+}
+
+pub fn mal_first(args: &[MalType]) -> MalRet {
+    Ok(seq_items("first", &args[0])?
+        .first()
+        .unwrap_or_default()
+        .clone())
+}
+
+pub fn mal_rest(args: &[MalType]) -> MalRet {
+    Ok(M::List(seq_items("rest", &args[0])?.rest()))
+}
+
+/// The items of the collection as a list, nil if there are none
+pub fn mal_seq(args: &[MalType]) -> MalRet {
+    let items = seq_items("seq", &args[0])?;
+    Ok(match items.is_empty() {
+        true => M::Nil,
+        false => M::List(items),
+    })
+}
+
+/// (apply f arg ... coll), the items of the last argument are passed after
+/// the other arguments
+pub fn mal_apply(args: &[MalType]) -> MalRet {
+    let (func, args) = car_cdr(args)?;
+    let mut call_args = args.to_vec();
+    if let Some(last) = call_args.pop() {
+        call_args.extend(seq_items("apply", &last)?.iter().cloned());
+    }
+    apply_fun(func, &call_args)
+}
+
+pub fn mal_concat(args: &[MalType]) -> MalRet {
+    let mut ret = Vec::new();
+    for coll in args {
+        ret.extend(seq_items("concat", coll)?.iter().cloned());
+    }
+    Ok(M::List(ret.into()))
+}
+
+/// Number of items in a list or vector, of entries in a map or of elements
+/// in a set
+pub fn mal_count(args: &[MalType]) -> MalRet {
+    let count = match car(args)? {
+        M::Map(map) => map.size(),
+        M::Set(set) => set.size(),
+        coll => coll.if_list()?.len(),
+    };
+    Ok(M::Num(Frac::num(count as isize)))
+}
+
+pub fn mal_hash_set(args: &[MalType]) -> MalRet {
+    Ok(M::Set(args.iter().cloned().collect()))
+}
+
+/// The items of the collection as a set, see "seq"
+pub fn mal_set(args: &[MalType]) -> MalRet {
+    Ok(M::Set(
+        seq_items("set", &args[0])?.iter().cloned().collect(),
+    ))
+}
+
+/// (disj set item ...)
+pub fn mal_disj(args: &[MalType]) -> MalRet {
+    let (set, items) = car_cdr(args)?;
+    let mut set = set_arg("disj", set)?;
+    for item in items {
+        set.remove_mut(item);
+    }
+    Ok(M::Set(set))
+}
+
+/// Elements in any of the sets
+pub fn mal_union(args: &[MalType]) -> MalRet {
+    let mut ret = MalSet::new();
+    for set in args {
+        set_arg("union", set)?
+            .iter()
+            .for_each(|item| ret.insert_mut(item.clone()));
+    }
+    Ok(M::Set(ret))
+}
+
+/// Elements of the first set that are in all the others
+pub fn mal_intersection(args: &[MalType]) -> MalRet {
+    let (first, others) = car_cdr(args)?;
+    let others = others
+        .iter()
+        .map(|set| set_arg("intersection", set))
+        .collect::<Result<Vec<_>, MalErr>>()?;
+    Ok(M::Set(
+        set_arg("intersection", first)?
+            .iter()
+            .filter(|item| others.iter().all(|set| set.contains(*item)))
+            .cloned()
+            .collect(),
+    ))
+}
+
+/// Elements of the first set that are in none of the others
+pub fn mal_difference(args: &[MalType]) -> MalRet {
+    let (first, others) = car_cdr(args)?;
+    let mut ret = set_arg("difference", first)?;
+    for set in others {
+        set_arg("difference", set)?.iter().for_each(|item| {
+            ret.remove_mut(item);
+        });
+    }
+    Ok(M::Set(ret))
+}
+
+/// (subset? a b), true if all the elements of a are in b
+pub fn mal_subset(args: &[MalType]) -> MalRet {
+    let (a, b) = (set_arg("subset?", &args[0])?, set_arg("subset?", &args[1])?);
+    Ok(match a.is_subset(&b) {
+        true => M::T,
+        false => M::Nil,
+    })
+}
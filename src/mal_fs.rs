@@ -0,0 +1,128 @@
+// Filesystem builtins, errors carry the text of the underlying OS error
+
+use crate::printer::pr_str;
+use crate::types::{Frac, MalErr, MalMap, MalRet, MalType};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use MalType as M;
+
+fn os_error(name: &str, path: &str) -> impl FnOnce(io::Error) -> MalErr {
+    let message = format!("{name}: {path}");
+    move |e| MalErr::unrecoverable(format!("{message}: {e}").as_str())
+}
+
+/// Write the content to the file, replacing it, strings are written as they
+/// are, anything else as printed by "str"
+pub fn spit(path: String, content: MalType) -> Result<(), MalErr> {
+    fs::write(&path, pr_str(&content, false)).map_err(os_error("spit", &path))
+}
+
+/// Write the content at the end of the file, creating it if needed
+pub fn spit_append(path: String, content: MalType) -> Result<(), MalErr> {
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut file| file.write_all(pr_str(&content, false).as_bytes()))
+        .map_err(os_error("spit-append", &path))
+}
+
+pub fn file_exists(path: String) -> Result<bool, MalErr> {
+    Ok(Path::new(&path).exists())
+}
+
+/// Delete a file or an empty directory
+pub fn delete_file(path: String) -> Result<(), MalErr> {
+    match Path::new(&path).is_dir() {
+        true => fs::remove_dir(&path),
+        false => fs::remove_file(&path),
+    }
+    .map_err(os_error("delete-file", &path))
+}
+
+pub fn rename_file(from: String, to: String) -> Result<(), MalErr> {
+    fs::rename(&from, &to).map_err(os_error("rename-file", &from))
+}
+
+/// Create a directory, along with any missing parent
+pub fn make_dir(path: String) -> Result<(), MalErr> {
+    fs::create_dir_all(&path).map_err(os_error("make-dir", &path))
+}
+
+/// Names of the entries in a directory, sorted
+pub fn list_dir(path: String) -> Result<Vec<String>, MalErr> {
+    let mut names = fs::read_dir(&path)
+        .and_then(|entries| {
+            entries
+                .map(|entry| Ok(entry?.file_name().to_string_lossy().to_string()))
+                .collect::<Result<Vec<String>, io::Error>>()
+        })
+        .map_err(os_error("list-dir", &path))?;
+    names.sort();
+    Ok(names)
+}
+
+/// Map with the size in bytes, the modification time in seconds since the
+/// epoch and whether the path is a directory
+pub fn file_info(path: String) -> MalRet {
+    let meta = fs::metadata(&path).map_err(os_error("file-info", &path))?;
+    let mtime = meta
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(M::Nil, |time| M::Num(Frac::integer(time.as_secs() as i128)));
+    let mut map = MalMap::new();
+    map.insert_mut(
+        M::Key(":size".into()),
+        M::Num(Frac::integer(meta.len() as i128)),
+    );
+    map.insert_mut(M::Key(":mtime".into()), mtime);
+    map.insert_mut(
+        M::Key(":dir?".into()),
+        if meta.is_dir() { M::T } else { M::Nil },
+    );
+    Ok(M::Map(map))
+}
+
+/// Join all the arguments as components of a single path
+pub fn path_join(args: &[MalType]) -> MalRet {
+    let mut path = PathBuf::new();
+    for component in args {
+        path.push(component.if_string()?);
+    }
+    Ok(M::Str(path.to_string_lossy().into()))
+}
+
+/// Directory containing the path, "." for a bare name, nil for the root or
+/// an empty path
+pub fn path_dir(path: String) -> Result<Option<String>, MalErr> {
+    Ok(Path::new(&path).parent().map(|dir| match dir.as_os_str() {
+        dir if dir.is_empty() => ".".to_string(),
+        dir => dir.to_string_lossy().to_string(),
+    }))
+}
+
+/// Last component of the path
+pub fn path_name(path: String) -> Result<Option<String>, MalErr> {
+    Ok(Path::new(&path)
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string()))
+}
+
+/// Extension of the last component of the path, without the dot
+pub fn path_ext(path: String) -> Result<Option<String>, MalErr> {
+    Ok(Path::new(&path)
+        .extension()
+        .map(|ext| ext.to_string_lossy().to_string()))
+}
+
+/// Absolute path with no "." or ".." components and symbolic links resolved,
+/// the path must exist
+pub fn path_absolute(path: String) -> Result<String, MalErr> {
+    fs::canonicalize(&path)
+        .map(|path| path.to_string_lossy().to_string())
+        .map_err(os_error("path-absolute", &path))
+}
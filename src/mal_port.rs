@@ -0,0 +1,241 @@
+// Ports: files, standard streams and strings read or written a piece at a time
+
+use crate::convert::FromMal;
+use crate::printer::{pr_str, prt};
+use crate::types::{MalErr, MalRet, MalStr, MalType};
+use std::cell::RefCell;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, BufWriter, Cursor, Write};
+use std::rc::Rc;
+
+use MalType as M;
+
+enum Stream {
+    Stdin,
+    Stdout,
+    Input(Box<dyn BufRead>),
+    Output(Box<dyn Write>),
+    // Output kept in memory, see "get-output-string"
+    String(String),
+    Closed,
+}
+
+/// Input or output stream, reading at the end of the input returns nil
+pub struct Port {
+    pub name: MalStr,
+    pub input: bool,
+    stream: RefCell<Stream>,
+}
+
+impl Port {
+    fn new(name: &str, input: bool, stream: Stream) -> Self {
+        Self {
+            name: name.into(),
+            input,
+            stream: RefCell::new(stream),
+        }
+    }
+
+    pub fn stdin() -> Self {
+        Self::new("stdin", true, Stream::Stdin)
+    }
+
+    pub fn stdout() -> Self {
+        Self::new("stdout", false, Stream::Stdout)
+    }
+
+    fn error(&self, e: impl std::fmt::Display) -> MalErr {
+        MalErr::unrecoverable(format!("{}: {e}", self.name).as_str())
+    }
+
+    fn with_input<R>(
+        &self,
+        read: impl FnOnce(&mut dyn BufRead) -> io::Result<R>,
+    ) -> Result<R, MalErr> {
+        match &mut *self.stream.borrow_mut() {
+            Stream::Stdin => read(&mut io::stdin().lock()),
+            Stream::Input(input) => read(input.as_mut()),
+            Stream::Closed => return Err(self.error("port is closed")),
+            _ => return Err(self.error("not an input port")),
+        }
+        .map_err(|e| self.error(e))
+    }
+
+    /// Next line without its terminator, None at the end of the input
+    pub fn read_line(&self) -> Result<Option<String>, MalErr> {
+        self.with_input(|input| {
+            let mut line = String::new();
+            if input.read_line(&mut line)? == 0 {
+                return Ok(None);
+            }
+            if line.ends_with('\n') {
+                line.pop();
+                if line.ends_with('\r') {
+                    line.pop();
+                }
+            }
+            Ok(Some(line))
+        })
+    }
+
+    /// Next character, None at the end of the input
+    pub fn read_char(&self) -> Result<Option<char>, MalErr> {
+        self.with_input(|input| {
+            let mut buf = [0; 4];
+            if input.read(&mut buf[..1])? == 0 {
+                return Ok(None);
+            }
+            // Length of the UTF-8 sequence from its first byte
+            let len = match buf[0] {
+                0xF0.. => 4,
+                0xE0.. => 3,
+                0xC0.. => 2,
+                _ => 1,
+            };
+            input.read_exact(&mut buf[1..len])?;
+            std::str::from_utf8(&buf[..len])
+                .map(|s| s.chars().next())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+    }
+
+    pub fn write(&self, content: &str) -> Result<(), MalErr> {
+        match &mut *self.stream.borrow_mut() {
+            Stream::Stdout => {
+                let mut stdout = io::stdout();
+                stdout
+                    .write_all(content.as_bytes())
+                    .and_then(|_| stdout.flush())
+            }
+            Stream::Output(output) => output.write_all(content.as_bytes()),
+            Stream::String(output) => {
+                output.push_str(content);
+                Ok(())
+            }
+            Stream::Closed => return Err(self.error("port is closed")),
+            _ => return Err(self.error("not an output port")),
+        }
+        .map_err(|e| self.error(e))
+    }
+
+    /// Content written so far to a string port
+    pub fn output_string(&self) -> Result<String, MalErr> {
+        match &*self.stream.borrow() {
+            Stream::String(output) => Ok(output.clone()),
+            _ => Err(self.error("not an output string port")),
+        }
+    }
+
+    /// Flush pending output and release the stream, standard streams are
+    /// never closed
+    pub fn close(&self) -> Result<(), MalErr> {
+        let mut stream = self.stream.borrow_mut();
+        match &mut *stream {
+            Stream::Stdin | Stream::Stdout => return Ok(()),
+            Stream::Output(output) => output.flush().map_err(|e| self.error(e))?,
+            _ => (),
+        }
+        *stream = Stream::Closed;
+        Ok(())
+    }
+}
+
+impl From<Port> for MalType {
+    fn from(port: Port) -> Self {
+        M::Port(Rc::new(port))
+    }
+}
+
+fn port_arg(name: &str, value: Option<&MalType>) -> Result<Rc<Port>, MalErr> {
+    match value {
+        Some(M::Port(port)) => Ok(port.clone()),
+        Some(value) => Err(MalErr::unrecoverable(
+            format!("{name}: {:?} is not a port", prt(value)).as_str(),
+        )),
+        None => Err(MalErr::unrecoverable(
+            format!("{name}: missing port").as_str(),
+        )),
+    }
+}
+
+// Reading without a port reads from stdin
+fn input_port(name: &str, args: &[MalType]) -> Result<Rc<Port>, MalErr> {
+    match args.first() {
+        None => Ok(Rc::new(Port::stdin())),
+        value => port_arg(name, value),
+    }
+}
+
+/// (open path [:read|:write|:append]), files are read by default
+pub fn mal_open(args: &[MalType]) -> MalRet {
+    let path = String::from_mal(&args[0])?;
+    let mode = args.get(1).map_or(":read".to_string(), prt);
+    let os_error = |e| MalErr::unrecoverable(format!("open: {path}: {e}").as_str());
+    match mode.as_str() {
+        ":read" => {
+            let file = File::open(&path).map_err(os_error)?;
+            Ok(Port::new(&path, true, Stream::Input(Box::new(BufReader::new(file)))).into())
+        }
+        ":write" | ":append" => {
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(mode == ":append")
+                .truncate(mode == ":write")
+                .open(&path)
+                .map_err(os_error)?;
+            Ok(Port::new(&path, false, Stream::Output(Box::new(BufWriter::new(file)))).into())
+        }
+        _ => Err(MalErr::unrecoverable(
+            format!("open: unknown mode {mode}").as_str(),
+        )),
+    }
+}
+
+pub fn mal_open_input_string(content: String) -> MalRet {
+    Ok(Port::new(
+        "string",
+        true,
+        Stream::Input(Box::new(Cursor::new(content))),
+    )
+    .into())
+}
+
+pub fn mal_open_output_string() -> MalRet {
+    Ok(Port::new("string", false, Stream::String(String::new())).into())
+}
+
+pub fn mal_get_output_string(args: &[MalType]) -> MalRet {
+    let port = port_arg("get-output-string", args.first())?;
+    Ok(M::Str(port.output_string()?.into()))
+}
+
+/// Read a line from the port, or from stdin, nil at the end of the input
+pub fn mal_read_line(args: &[MalType]) -> MalRet {
+    let line = input_port("read-line", args)?.read_line()?;
+    Ok(line.map_or(M::Nil, |line| M::Str(line.into())))
+}
+
+/// Read a character from the port, or from stdin, nil at the end of the input
+pub fn mal_read_char(args: &[MalType]) -> MalRet {
+    let c = input_port("read-char", args)?.read_char()?;
+    Ok(c.map_or(M::Nil, M::Ch))
+}
+
+/// Write the values to the port, formatted as "str" does
+pub fn mal_write(args: &[MalType]) -> MalRet {
+    let port = port_arg("write", args.first())?;
+    port.write(
+        &args[1..]
+            .iter()
+            .map(|value| pr_str(value, false))
+            .collect::<String>(),
+    )?;
+    Ok(M::Nil)
+}
+
+pub fn mal_close(args: &[MalType]) -> MalRet {
+    let port = port_arg("close", args.first())?;
+    port.close()?;
+    Ok(M::Nil)
+}
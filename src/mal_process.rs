@@ -0,0 +1,82 @@
+// Running other programs, without going through a shell
+
+use crate::convert::FromMal;
+use crate::types::{Frac, MalErr, MalMap, MalRet, MalType};
+use std::collections::HashMap;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use MalType as M;
+
+/// (run program [args] {:in :dir :env}), the program is executed directly
+/// with the arguments given (nil for none), returns a map with :exit, :out
+/// and :err
+pub fn mal_run(args: &[MalType]) -> MalRet {
+    let program = String::from_mal(&args[0])?;
+    let mut command = Command::new(&program);
+    if let Some(list) = args.get(1) {
+        command.args(Option::<Vec<String>>::from_mal(list)?.unwrap_or_default());
+    }
+
+    let mut input = None;
+    if let Some(opts) = args.get(2) {
+        let opts = HashMap::<String, MalType>::from_mal(opts)?;
+        for (key, value) in opts {
+            match key.as_str() {
+                ":in" => input = Some(String::from_mal(&value)?),
+                ":dir" => _ = command.current_dir(String::from_mal(&value)?),
+                ":env" => _ = command.envs(HashMap::<String, String>::from_mal(&value)?),
+                _ => {
+                    return Err(MalErr::unrecoverable(
+                        format!("run: unknown option {key}").as_str(),
+                    ))
+                }
+            }
+        }
+    }
+
+    let os_error = |e| MalErr::unrecoverable(format!("run: {program}: {e}").as_str());
+    let mut child = command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::null()
+        })
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(os_error)?;
+
+    // Input is written on its own thread, the program may fill its output
+    // pipes before reading all of it
+    let writer = match (input, child.stdin.take()) {
+        (Some(input), Some(mut stdin)) => {
+            Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+        }
+        _ => None,
+    };
+    let output = child.wait_with_output().map_err(os_error)?;
+    if let Some(writer) = writer {
+        // The program is allowed to exit without reading its input
+        let _ = writer.join();
+    }
+
+    let mut map = MalMap::new();
+    map.insert_mut(
+        M::Key(":exit".into()),
+        output
+            .status
+            .code()
+            .map_or(M::Nil, |code| M::Num(Frac::integer(code as i128))),
+    );
+    map.insert_mut(
+        M::Key(":out".into()),
+        M::Str(String::from_utf8_lossy(&output.stdout).into()),
+    );
+    map.insert_mut(
+        M::Key(":err".into()),
+        M::Str(String::from_utf8_lossy(&output.stderr).into()),
+    );
+    Ok(M::Map(map))
+}
@@ -0,0 +1,114 @@
+// Regular expressions, compiled once by re-pattern and used as values
+
+use crate::convert::{FromMal, IntoMal};
+use crate::printer::prt;
+use crate::types::{MalErr, MalType};
+use regex::{Captures, Regex};
+use std::cell::OnceCell;
+use std::ops::Deref;
+use std::rc::Rc;
+
+use MalType as M;
+
+fn compile(pattern: &str) -> Result<Regex, MalErr> {
+    Regex::new(pattern).map_err(|e| MalErr::unrecoverable(e.to_string().as_str()))
+}
+
+/// Compiled regex, along with its form anchored to the whole string, compiled
+/// the first time re-matches needs it
+pub struct Pattern {
+    re: Regex,
+    anchored: OnceCell<Regex>,
+}
+
+impl Pattern {
+    pub fn new(pattern: &str) -> Result<Self, MalErr> {
+        Ok(Self {
+            re: compile(pattern)?,
+            anchored: OnceCell::new(),
+        })
+    }
+
+    fn anchored(&self) -> Result<&Regex, MalErr> {
+        if let Some(anchored) = self.anchored.get() {
+            return Ok(anchored);
+        }
+        let anchored = compile(format!(r"\A(?:{})\z", self.re.as_str()).as_str())?;
+        Ok(self.anchored.get_or_init(|| anchored))
+    }
+}
+
+impl Deref for Pattern {
+    type Target = Regex;
+
+    fn deref(&self) -> &Regex {
+        &self.re
+    }
+}
+
+// Strings are accepted too, compiling them on every call
+impl FromMal for Rc<Pattern> {
+    fn from_mal(value: &MalType) -> Result<Self, MalErr> {
+        match value {
+            M::Regex(re) => Ok(re.clone()),
+            M::Str(s) => Pattern::new(s).map(Rc::new),
+            _ => Err(MalErr::unrecoverable(
+                format!("{:?} is not a regex", prt(value)).as_str(),
+            )),
+        }
+    }
+}
+
+impl IntoMal for Rc<Pattern> {
+    fn into_mal(self) -> MalType {
+        M::Regex(self)
+    }
+}
+
+// The matched string if the regex has no groups, otherwise a vector with the
+// match followed by the groups, nil for the ones that did not participate
+fn groups(caps: Captures) -> MalType {
+    if caps.len() == 1 {
+        return caps[0].into_mal();
+    }
+    M::Vector(
+        caps.iter()
+            .map(|group| group.map(|m| m.as_str()).into_mal())
+            .collect(),
+    )
+}
+
+pub fn re_pattern(re: Rc<Pattern>) -> Result<Rc<Pattern>, MalErr> {
+    Ok(re)
+}
+
+/// First match in the string
+pub fn re_find(re: Rc<Pattern>, s: String) -> Result<MalType, MalErr> {
+    Ok(re.captures(&s).map_or(M::Nil, groups))
+}
+
+/// Match only if the whole string matches
+pub fn re_matches(re: Rc<Pattern>, s: String) -> Result<MalType, MalErr> {
+    match re.captures(&s) {
+        Some(caps) if caps[0].len() == s.len() => Ok(groups(caps)),
+        None => Ok(M::Nil),
+        // A shorter alternative may have been preferred ("a|ab" on "ab"),
+        // only an anchored regex can tell
+        Some(_) => Ok(re.anchored()?.captures(&s).map_or(M::Nil, groups)),
+    }
+}
+
+/// All the non overlapping matches in the string
+pub fn re_seq(re: Rc<Pattern>, s: String) -> Result<MalType, MalErr> {
+    Ok(M::List(re.captures_iter(&s).map(groups).collect()))
+}
+
+pub fn re_split(re: Rc<Pattern>, s: String) -> Result<Vec<String>, MalErr> {
+    Ok(re.split(&s).map(String::from).collect())
+}
+
+/// Replace all the matches, "$1" or "${name}" in the replacement are the
+/// groups of each match
+pub fn re_replace(re: Rc<Pattern>, s: String, replacement: String) -> Result<String, MalErr> {
+    Ok(re.replace_all(&s, replacement.as_str()).to_string())
+}
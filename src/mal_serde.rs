@@ -0,0 +1,868 @@
+// Serde support: any serializable Rust value can be turned into a mal value
+// and back. Structs become maps with keyword keys, sequences become vectors
+// and enum variants are tagged by keywords, {:variant content} if they carry
+// any data. Sets are serialized as sequences
+
+use crate::env::{car, car_cdr};
+use crate::mal_coll::{as_index, set_items};
+use crate::printer::prt;
+use crate::types::{Frac, MalArgs, MalErr, MalMap, MalRet, MalType};
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, MapAccess, SeqAccess,
+    VariantAccess, Visitor,
+};
+use serde::ser::{self, Serialize};
+use serde::{forward_to_deserialize_any, Deserialize, Deserializer, Serializer};
+use serde_json::ser::PrettyFormatter;
+use std::collections::HashSet;
+use std::fmt;
+
+use MalType as M;
+
+impl ser::Error for MalErr {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        MalErr::unrecoverable(msg.to_string().as_str())
+    }
+}
+
+impl de::Error for MalErr {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        MalErr::unrecoverable(msg.to_string().as_str())
+    }
+}
+
+/// Convert any serializable value to a mal value
+pub fn to_mal<T: Serialize + ?Sized>(value: &T) -> Result<MalType, MalErr> {
+    value.serialize(MalSerializer)
+}
+
+/// Extract any deserializable value from a mal value
+pub fn from_value<T: DeserializeOwned>(value: &MalType) -> Result<T, MalErr> {
+    T::deserialize(value.clone())
+}
+
+/// Parse a JSON document: objects become maps, arrays vectors, false and
+/// null are both nil
+pub fn mal_json_parse(args: &[MalType]) -> MalRet {
+    serde_json::from_str(car(args)?.if_string()?)
+        .map_err(|e| MalErr::unrecoverable(format!("json-parse: {e}").as_str()))
+}
+
+// Indentations are capped as JSON.stringify does
+const MAX_INDENT: usize = 10;
+
+/// Write a value as JSON, indented by the given number of spaces if any
+pub fn mal_json_stringify(args: &[MalType]) -> MalRet {
+    let (value, indent) = car_cdr(args)?;
+    let mut out = Vec::new();
+    let res = match indent.first() {
+        Some(indent) => {
+            let indent = match as_index(indent) {
+                Some(indent) => " ".repeat(indent.min(MAX_INDENT)),
+                None => {
+                    return Err(MalErr::unrecoverable(
+                        format!(
+                            "json-stringify: {:?} is not a valid indentation",
+                            prt(indent)
+                        )
+                        .as_str(),
+                    ))
+                }
+            };
+            let formatter = PrettyFormatter::with_indent(indent.as_bytes());
+            value.serialize(&mut serde_json::Serializer::with_formatter(
+                &mut out, formatter,
+            ))
+        }
+        None => value.serialize(&mut serde_json::Serializer::new(&mut out)),
+    };
+    res.map_err(|e| MalErr::unrecoverable(format!("json-stringify: {e}").as_str()))?;
+    Ok(M::Str(String::from_utf8_lossy(&out).into()))
+}
+
+// Keywords are seen by serde as their name, without the colon
+fn key_name(key: &str) -> &str {
+    key.strip_prefix(':').unwrap_or(key)
+}
+
+fn keyword(name: &str) -> MalType {
+    M::Key(format!(":{name}").into())
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// MalType -> any format                                                      //
+////////////////////////////////////////////////////////////////////////////////
+
+// Exact integers are serialized as integers, anything else as a float
+fn serialize_number<S: Serializer>(val: &Frac, serializer: S) -> Result<S::Ok, S::Error> {
+    match val.to_i128() {
+        Some(int) => match (i64::try_from(int), u64::try_from(int)) {
+            (Ok(int), _) => serializer.serialize_i64(int),
+            (_, Ok(int)) => serializer.serialize_u64(int),
+            _ => serializer.serialize_i128(int),
+        },
+        None => serializer.serialize_f64(val.to_f64()),
+    }
+}
+
+// Keywords lose their colon and symbols their type, returns the name shared
+// by keys that would overwrite each other once serialized
+fn duplicate_key(map: &MalMap) -> Option<&str> {
+    let mut names = HashSet::new();
+    map.keys()
+        .filter_map(|key| match key {
+            M::Str(s) | M::Sym(s) => Some(s.as_ref()),
+            M::Key(k) => Some(key_name(k)),
+            _ => None,
+        })
+        .find(|name| !names.insert(*name))
+}
+
+impl Serialize for MalType {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            M::Nil => serializer.serialize_unit(),
+            M::T => serializer.serialize_bool(true),
+            M::Num(val) => serialize_number(val, serializer),
+            M::Str(s) | M::Sym(s) => serializer.serialize_str(s),
+            M::Key(k) => serializer.serialize_str(key_name(k)),
+            M::Ch(c) => serializer.serialize_char(*c),
+            M::List(list) | M::Vector(list) => serializer.collect_seq(list.iter()),
+            M::Map(map) => match duplicate_key(map) {
+                Some(name) => Err(ser::Error::custom(format!(
+                    "{name:?} is used by more than one key"
+                ))),
+                None => serializer.collect_map(map),
+            },
+            M::Set(set) => serializer.collect_seq(set_items(set)),
+            M::Atom(a) => a.borrow().serialize(serializer),
+            M::Fun(..) | M::Native(_) | M::MalFun { .. } | M::Port(_) | M::Regex(_) => Err(
+                ser::Error::custom(format!("{:?} cannot be serialized", prt(self))),
+            ),
+        }
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Any format -> MalType                                                      //
+////////////////////////////////////////////////////////////////////////////////
+
+struct MalVisitor;
+
+impl<'de> Visitor<'de> for MalVisitor {
+    type Value = MalType;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a value representable in mal")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> Result<MalType, E> {
+        Ok(if v { M::T } else { M::Nil })
+    }
+
+    fn visit_i64<E>(self, v: i64) -> Result<MalType, E> {
+        Ok(M::Num(Frac::integer(v.into())))
+    }
+
+    fn visit_i128<E>(self, v: i128) -> Result<MalType, E> {
+        Ok(M::Num(Frac::integer(v)))
+    }
+
+    fn visit_u64<E>(self, v: u64) -> Result<MalType, E> {
+        Ok(M::Num(Frac::integer(v.into())))
+    }
+
+    fn visit_f64<E>(self, v: f64) -> Result<MalType, E> {
+        Ok(M::Num(Frac::inexact(v)))
+    }
+
+    fn visit_char<E>(self, v: char) -> Result<MalType, E> {
+        Ok(M::Ch(v))
+    }
+
+    fn visit_str<E>(self, v: &str) -> Result<MalType, E> {
+        Ok(M::Str(v.into()))
+    }
+
+    fn visit_unit<E>(self) -> Result<MalType, E> {
+        Ok(M::Nil)
+    }
+
+    fn visit_none<E>(self) -> Result<MalType, E> {
+        Ok(M::Nil)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, d: D) -> Result<MalType, D::Error> {
+        MalType::deserialize(d)
+    }
+
+    fn visit_newtype_struct<D: Deserializer<'de>>(self, d: D) -> Result<MalType, D::Error> {
+        MalType::deserialize(d)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<MalType, A::Error> {
+        let mut list = Vec::new();
+        while let Some(el) = seq.next_element()? {
+            list.push(el);
+        }
+        Ok(M::Vector(list.into()))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<MalType, A::Error> {
+        let mut map = MalMap::new();
+        while let Some((k, v)) = access.next_entry::<MalType, MalType>()? {
+            map.insert_mut(k, v);
+        }
+        Ok(M::Map(map))
+    }
+}
+
+impl<'de> Deserialize<'de> for MalType {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MalVisitor)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Rust values -> MalType                                                     //
+////////////////////////////////////////////////////////////////////////////////
+
+struct MalSerializer;
+
+// Wrap the content of an enum variant in {:variant content}
+fn tagged(variant: &str, content: MalType) -> MalType {
+    let mut map = MalMap::new();
+    map.insert_mut(keyword(variant), content);
+    M::Map(map)
+}
+
+impl Serializer for MalSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = SeqSerializer;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = MapSerializer;
+    type SerializeStructVariant = MapSerializer;
+
+    fn serialize_bool(self, v: bool) -> Result<MalType, MalErr> {
+        Ok(if v { M::T } else { M::Nil })
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<MalType, MalErr> {
+        Ok(M::Num(Frac::integer(v)))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<MalType, MalErr> {
+        self.serialize_i128(v.into())
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<MalType, MalErr> {
+        self.serialize_f64(v.into())
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<MalType, MalErr> {
+        Ok(M::Num(Frac::inexact(v)))
+    }
+
+    fn serialize_char(self, v: char) -> Result<MalType, MalErr> {
+        Ok(M::Ch(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<MalType, MalErr> {
+        Ok(M::Str(v.into()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<MalType, MalErr> {
+        Ok(M::Vector(
+            v.iter().map(|b| M::Num(Frac::num(*b as isize))).collect(),
+        ))
+    }
+
+    fn serialize_none(self) -> Result<MalType, MalErr> {
+        Ok(M::Nil)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<MalType, MalErr> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<MalType, MalErr> {
+        Ok(M::Nil)
+    }
+
+    fn serialize_unit_struct(self, _: &'static str) -> Result<MalType, MalErr> {
+        Ok(M::Nil)
+    }
+
+    fn serialize_unit_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+    ) -> Result<MalType, MalErr> {
+        Ok(keyword(variant))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        value: &T,
+    ) -> Result<MalType, MalErr> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<MalType, MalErr> {
+        Ok(tagged(variant, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer, MalErr> {
+        Ok(SeqSerializer {
+            variant: None,
+            list: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer, MalErr> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _: &'static str, len: usize) -> Result<SeqSerializer, MalErr> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<SeqSerializer, MalErr> {
+        Ok(SeqSerializer {
+            variant: Some(variant),
+            list: Vec::with_capacity(len),
+        })
+    }
+
+    fn serialize_map(self, _: Option<usize>) -> Result<MapSerializer, MalErr> {
+        Ok(MapSerializer {
+            variant: None,
+            map: MalMap::new(),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, _: &'static str, _: usize) -> Result<MapSerializer, MalErr> {
+        self.serialize_map(None)
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _: &'static str,
+        _: u32,
+        variant: &'static str,
+        _: usize,
+    ) -> Result<MapSerializer, MalErr> {
+        Ok(MapSerializer {
+            variant: Some(variant),
+            map: MalMap::new(),
+            key: None,
+        })
+    }
+}
+
+struct SeqSerializer {
+    variant: Option<&'static str>,
+    list: Vec<MalType>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalErr> {
+        self.list.push(value.serialize(MalSerializer)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<MalType, MalErr> {
+        let list = M::Vector(self.list.into());
+        Ok(match self.variant {
+            Some(variant) => tagged(variant, list),
+            None => list,
+        })
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for SeqSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalErr> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+struct MapSerializer {
+    variant: Option<&'static str>,
+    map: MalMap,
+    key: Option<MalType>,
+}
+
+impl MapSerializer {
+    fn finish(self) -> Result<MalType, MalErr> {
+        let map = M::Map(self.map);
+        Ok(match self.variant {
+            Some(variant) => tagged(variant, map),
+            None => map,
+        })
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), MalErr> {
+        self.key = Some(key.serialize(MalSerializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), MalErr> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| MalErr::unrecoverable("Map value without a key"))?;
+        self.map.insert_mut(key, value.serialize(MalSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStruct for MapSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), MalErr> {
+        self.map
+            .insert_mut(keyword(key), value.serialize(MalSerializer)?);
+        Ok(())
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for MapSerializer {
+    type Ok = MalType;
+    type Error = MalErr;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), MalErr> {
+        ser::SerializeStruct::serialize_field(self, key, value)
+    }
+
+    fn end(self) -> Result<MalType, MalErr> {
+        self.finish()
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// MalType -> Rust values                                                     //
+////////////////////////////////////////////////////////////////////////////////
+
+impl<'de> IntoDeserializer<'de, MalErr> for MalType {
+    type Deserializer = MalType;
+
+    fn into_deserializer(self) -> MalType {
+        self
+    }
+}
+
+impl<'de> Deserializer<'de> for MalType {
+    type Error = MalErr;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalErr> {
+        match self {
+            M::Nil => visitor.visit_unit(),
+            M::T => visitor.visit_bool(true),
+            M::Num(val) => match val.to_i128() {
+                Some(int) => match (i64::try_from(int), u64::try_from(int)) {
+                    (Ok(int), _) => visitor.visit_i64(int),
+                    (_, Ok(int)) => visitor.visit_u64(int),
+                    _ => visitor.visit_i128(int),
+                },
+                None => visitor.visit_f64(val.to_f64()),
+            },
+            M::Str(s) | M::Sym(s) => visitor.visit_str(&s),
+            M::Key(k) => visitor.visit_str(key_name(&k)),
+            M::Ch(c) => visitor.visit_char(c),
+            M::List(list) | M::Vector(list) => visitor.visit_seq(SeqDeserializer { list }),
+            M::Set(set) => visitor.visit_seq(SeqDeserializer {
+                list: set_items(&set).into_iter().cloned().collect(),
+            }),
+            M::Map(map) => visitor.visit_map(MapDeserializer {
+                iter: map
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect::<Vec<_>>()
+                    .into_iter(),
+                value: None,
+            }),
+            M::Atom(a) => a.borrow().clone().deserialize_any(visitor),
+            M::Fun(..) | M::Native(_) | M::MalFun { .. } | M::Port(_) | M::Regex(_) => Err(
+                MalErr::unrecoverable(format!("{:?} cannot be deserialized", prt(&self)).as_str()),
+            ),
+        }
+    }
+
+    // nil is also false
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalErr> {
+        match self {
+            M::Nil => visitor.visit_bool(false),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, MalErr> {
+        match self {
+            M::Nil => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, MalErr> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    // Keywords (or strings) for unit variants, {:variant content} otherwise
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _: &'static str,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, MalErr> {
+        match self {
+            M::Key(_) | M::Str(_) => visitor.visit_enum(EnumDeserializer {
+                variant: self,
+                content: None,
+            }),
+            M::Map(map) if map.size() == 1 => {
+                let (variant, content) = map.iter().next().unwrap();
+                visitor.visit_enum(EnumDeserializer {
+                    variant: variant.clone(),
+                    content: Some(content.clone()),
+                })
+            }
+            _ => Err(MalErr::unrecoverable(
+                format!("{:?} is not an enum variant", prt(&self)).as_str(),
+            )),
+        }
+    }
+
+    forward_to_deserialize_any! {
+        i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf unit unit_struct seq tuple tuple_struct map struct
+        identifier ignored_any
+    }
+}
+
+// Elements are taken from the front, dropping them is O(1)
+struct SeqDeserializer {
+    list: MalArgs,
+}
+
+impl<'de> SeqAccess<'de> for SeqDeserializer {
+    type Error = MalErr;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(
+        &mut self,
+        seed: T,
+    ) -> Result<Option<T::Value>, MalErr> {
+        let el = self.list.first().cloned();
+        self.list = self.list.rest();
+        el.map(|el| seed.deserialize(el)).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.list.len())
+    }
+}
+
+struct MapDeserializer {
+    iter: std::vec::IntoIter<(MalType, MalType)>,
+    value: Option<MalType>,
+}
+
+impl<'de> MapAccess<'de> for MapDeserializer {
+    type Error = MalErr;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, MalErr> {
+        match self.iter.next() {
+            Some((k, v)) => {
+                self.value = Some(v);
+                seed.deserialize(k).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value, MalErr> {
+        match self.value.take() {
+            Some(v) => seed.deserialize(v),
+            None => Err(MalErr::unrecoverable("Map key without a value")),
+        }
+    }
+}
+
+struct EnumDeserializer {
+    variant: MalType,
+    content: Option<MalType>,
+}
+
+impl<'de> EnumAccess<'de> for EnumDeserializer {
+    type Error = MalErr;
+    type Variant = Self;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Self), MalErr> {
+        Ok((seed.deserialize(self.variant.clone())?, self))
+    }
+}
+
+impl<'de> VariantAccess<'de> for EnumDeserializer {
+    type Error = MalErr;
+
+    fn unit_variant(self) -> Result<(), MalErr> {
+        match self.content {
+            None | Some(M::Nil) => Ok(()),
+            Some(content) => Err(MalErr::unrecoverable(
+                format!("Unexpected content for unit variant: {}", prt(&content)).as_str(),
+            )),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value, MalErr> {
+        seed.deserialize(self.content.unwrap_or(M::Nil))
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _: usize, visitor: V) -> Result<V::Value, MalErr> {
+        self.content.unwrap_or(M::Nil).deserialize_any(visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(
+        self,
+        _: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, MalErr> {
+        self.content.unwrap_or(M::Nil).deserialize_any(visitor)
+    }
+}
+
+////////////////////////////////////////////////////////////////////////////////
+// Tests                                                                      //
+////////////////////////////////////////////////////////////////////////////////
+
+#[cfg(test)]
+mod tests {
+    use super::{from_value, to_mal};
+    use crate::printer::pr_str;
+    use crate::reader::{read_str, Reader};
+    use crate::types::MalType;
+    use serde::{Deserialize, Serialize};
+    use std::collections::HashMap;
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    enum Shape {
+        Point,
+        Circle(f64),
+        Rect { w: u32, h: u32 },
+        Segment(i32, i32),
+    }
+
+    #[derive(Serialize, Deserialize, PartialEq, Debug)]
+    struct Config {
+        name: String,
+        port: u16,
+        ratio: f64,
+        debug: bool,
+        tags: Vec<String>,
+        shapes: Vec<Shape>,
+        limits: HashMap<String, i64>,
+        parent: Option<Box<Config>>,
+    }
+
+    fn read(input: &str) -> MalType {
+        read_str(Reader::new().push(input)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = Config {
+            name: "server".into(),
+            port: 8080,
+            ratio: 0.5,
+            debug: false,
+            tags: vec!["a".into(), "b".into()],
+            shapes: vec![
+                Shape::Point,
+                Shape::Circle(1.5),
+                Shape::Rect { w: 2, h: 3 },
+                Shape::Segment(-1, 1),
+            ],
+            limits: HashMap::from([("max".into(), 10)]),
+            parent: None,
+        };
+        let value = to_mal(&config).unwrap();
+        assert_eq!(from_value::<Config>(&value).unwrap(), config);
+    }
+
+    #[test]
+    fn from_source() {
+        // Structs from maps with keyword keys, enums from keywords
+        let value = read(
+            "{:name \"s\" :port 80 :ratio 1/4 :debug t :tags (\"x\") :limits {\"a\" -1}
+              :shapes [:Point {:Circle 2} {:Rect {:w 1 :h 2}} {:Segment [1 2]}]
+              :parent nil}",
+        );
+        let config = from_value::<Config>(&value).unwrap();
+        assert_eq!(config.ratio, 0.25);
+        assert!(config.debug);
+        assert_eq!(config.limits["a"], -1);
+        assert_eq!(
+            config.shapes,
+            vec![
+                Shape::Point,
+                Shape::Circle(2.0),
+                Shape::Rect { w: 1, h: 2 },
+                Shape::Segment(1, 2)
+            ]
+        );
+        // Only whole exact numbers are integers
+        assert!(from_value::<u16>(&read("1/2")).is_err());
+        assert!(from_value::<u8>(&read("256")).is_err());
+        assert!(from_value::<Config>(&read("{:name \"s\"}")).is_err());
+    }
+
+    #[test]
+    fn to_source() {
+        assert_eq!(pr_str(&to_mal(&Shape::Point).unwrap(), true), ":Point");
+        assert_eq!(
+            pr_str(&to_mal(&Shape::Segment(1, 2)).unwrap(), true),
+            "{:Segment [1 2]}"
+        );
+        assert_eq!(
+            pr_str(&to_mal(&(1, "a", None::<u8>)).unwrap(), true),
+            "[1 \"a\" NIL]"
+        );
+    }
+
+    #[test]
+    fn mal_values() {
+        // MalType itself goes through any serde format, here through itself
+        let value = read("{:a [1 1/2 \"s\"] :b (t nil)}");
+        let copy = to_mal(&value).unwrap();
+        assert_eq!(
+            from_value::<HashMap<String, MalType>>(&copy).unwrap().len(),
+            2
+        );
+        assert!(to_mal(&MalType::Fun(|_| Ok(MalType::Nil), "")).is_err());
+        // Fractions become floats, keys must not collide
+        assert_eq!(pr_str(&to_mal(&read("1/4")).unwrap(), true), "0.25");
+        assert!(to_mal(&read("{:a 1 \"a\" 2}")).is_err());
+        assert!(to_mal(&read("{:a 1 \"b\" 2}")).is_ok());
+    }
+}
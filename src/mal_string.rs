@@ -0,0 +1,99 @@
+// String builtins, positions and lengths are counted in characters
+
+use crate::printer::pr_str;
+use crate::types::{Frac, MalErr, MalType};
+
+fn out_of_range(name: &str, index: usize, len: usize) -> MalErr {
+    MalErr::unrecoverable(format!("{name}: index {index} out of range for length {len}").as_str())
+}
+
+// Byte offset of the character at the given position, the end of the string
+// is a valid position
+fn byte_offset(name: &str, s: &str, index: usize) -> Result<usize, MalErr> {
+    s.char_indices()
+        .map(|(offset, _)| offset)
+        .chain([s.len()])
+        .nth(index)
+        .ok_or_else(|| out_of_range(name, index, s.chars().count()))
+}
+
+pub fn string_length(s: String) -> Result<usize, MalErr> {
+    Ok(s.chars().count())
+}
+
+/// Characters from start up to end (excluded), or to the end of the string
+pub fn substring(s: String, start: usize, end: Option<usize>) -> Result<String, MalErr> {
+    let from = byte_offset("substring", &s, start)?;
+    let to = match end {
+        Some(end) if end < start => {
+            return Err(MalErr::unrecoverable(
+                format!("substring: end {end} is before start {start}").as_str(),
+            ))
+        }
+        Some(end) => byte_offset("substring", &s, end)?,
+        None => s.len(),
+    };
+    Ok(s[from..to].to_string())
+}
+
+/// Position of the first occurrence of sub, starting the search at from
+pub fn index_of(s: String, sub: String, from: Option<usize>) -> Result<Option<usize>, MalErr> {
+    let from = from.unwrap_or(0);
+    let offset = byte_offset("index-of", &s, from)?;
+    Ok(s[offset..]
+        .find(&sub)
+        .map(|found| from + s[offset..offset + found].chars().count()))
+}
+
+/// Split at every occurrence of the delimiter, an empty delimiter splits
+/// every character
+pub fn split(s: String, delimiter: String) -> Result<Vec<String>, MalErr> {
+    Ok(match delimiter.as_str() {
+        "" => s.chars().map(String::from).collect(),
+        delimiter => s.split(delimiter).map(String::from).collect(),
+    })
+}
+
+/// Join the elements of the list as "str" does, with an optional separator
+pub fn join(list: Vec<MalType>, separator: Option<String>) -> Result<String, MalErr> {
+    Ok(list
+        .iter()
+        .map(|el| pr_str(el, false))
+        .collect::<Vec<String>>()
+        .join(&separator.unwrap_or_default()))
+}
+
+pub fn trim(s: String) -> Result<String, MalErr> {
+    Ok(s.trim().to_string())
+}
+
+pub fn upper_case(s: String) -> Result<String, MalErr> {
+    Ok(s.to_uppercase())
+}
+
+pub fn lower_case(s: String) -> Result<String, MalErr> {
+    Ok(s.to_lowercase())
+}
+
+pub fn starts_with(s: String, prefix: String) -> Result<bool, MalErr> {
+    Ok(s.starts_with(&prefix))
+}
+
+pub fn ends_with(s: String, suffix: String) -> Result<bool, MalErr> {
+    Ok(s.ends_with(&suffix))
+}
+
+/// Replace every occurrence of from with to
+pub fn replace(s: String, from: String, to: String) -> Result<String, MalErr> {
+    Ok(s.replace(&from, &to))
+}
+
+/// The number written in the string, as the reader would read it, nil if
+/// the string is not a number
+pub fn string_to_number(s: String) -> Result<Option<Frac>, MalErr> {
+    Ok(s.trim().parse::<Frac>().ok())
+}
+
+pub fn number_to_string(n: Frac) -> Result<String, MalErr> {
+    Ok(n.to_string())
+}
@@ -3,16 +3,13 @@ mod functional {
 
     macro_rules! test {
         ($file:expr) => {{
-            use crate::core::ns_init;
-            use crate::load_file;
-            use crate::parse_tools::{load_home_file, set_home_path};
-            let env = ns_init();
-            set_home_path(&env);
-            load_home_file("core.mal", &env, false);
-            assert!(matches!(
-                load_file(format!("tests/{}.mal", $file).as_str(), &env),
-                Ok(_)
-            ));
+            use crate::Interpreter;
+            // Always test against the core shipped with the sources
+            let mal = Interpreter::new().unwrap();
+            mal.define("MAL_HOME", "core");
+            assert!(mal
+                .load_file(format!("tests/{}.mal", $file).as_str())
+                .is_ok());
         }};
     }
     // TODO: modify to accept more parameters for test/libraries
@@ -38,6 +35,11 @@ mod functional {
         test!("arithmetic")
     }
 
+    #[test]
+    fn float() {
+        test!("float")
+    }
+
     #[test]
     fn fibonacci() {
         test!("fibonacci")
@@ -72,4 +74,99 @@ mod functional {
     fn fil() {
         test!("fil")
     }
+
+    #[test]
+    fn macros() {
+        test!("macros")
+    }
+
+    #[test]
+    fn quasiquote() {
+        test!("quasiquote")
+    }
+
+    #[test]
+    fn try_catch() {
+        test!("try")
+    }
+
+    #[test]
+    fn arity() {
+        test!("arity")
+    }
+
+    #[test]
+    fn destructuring() {
+        test!("destructuring")
+    }
+
+    #[test]
+    fn loop_recur() {
+        test!("loop")
+    }
+
+    #[test]
+    fn locations() {
+        test!("locations")
+    }
+
+    #[test]
+    fn trace() {
+        test!("trace")
+    }
+
+    #[test]
+    fn json() {
+        test!("json")
+    }
+
+    #[test]
+    fn edn() {
+        test!("edn")
+    }
+
+    #[test]
+    fn fs() {
+        test!("fs")
+    }
+
+    #[test]
+    fn run() {
+        test!("run")
+    }
+
+    #[test]
+    fn ports() {
+        test!("ports")
+    }
+
+    #[test]
+    fn strings() {
+        test!("strings")
+    }
+
+    #[test]
+    fn regex() {
+        test!("regex")
+    }
+
+    #[test]
+    fn maps() {
+        test!("maps")
+    }
+
+    #[test]
+    fn long_lists() {
+        test!("long-lists")
+    }
+
+    #[test]
+    fn collections() {
+        test!("collections")
+    }
+
+    #[test]
+    fn sets() {
+        test!("sets")
+    }
 }
@@ -1,149 +1,33 @@
-use crate::env::Env;
-use crate::eval::eval;
-use crate::reader::{read_str, Reader};
-use crate::step6_file::rep;
-use crate::types::{MalErr, MalRet, MalStr};
+use crate::reader::{read_all, Reader};
+use crate::types::{MalErr, MalRet, MalStr, MalType};
 use std::fs::File;
 use std::io::Read;
-use std::path::Path;
-use std::process::exit;
-
-fn eval_str(line: &str, env: &Env) -> MalRet {
-    eval(&read_str(Reader::new().push(line))?, env.clone())
-}
-
-pub fn set_home_path(env: &Env) {
-    eval_str(
-        "(if (env \"MAL_HOME\")
-        (def! MAL_HOME (env \"MAL_HOME\")) 
-        (def! MAL_HOME (str (env \"HOME\") \"/.config/mal\")))",
-        env,
-    )
-    .unwrap();
-}
-
-pub fn print_banner(env: &Env) {
-    let _ = eval_str("(prn BANNER)", env);
-}
-
-fn get_home_path(env: &Env) -> Result<String, MalErr> {
-    Ok(eval_str("MAL_HOME", env)?.if_string()?.to_string())
-}
-
-pub fn load_home_file(filename: &str, env: &Env, warn: bool) {
-    let full_filename = get_home_path(env).unwrap_or_else(|_| "".to_string()) + "/" + filename;
-
-    if Path::new(&full_filename).exists() {
-        if let Err(e) = load_file(&full_filename, env) {
-            eprintln!("; reading \"{full_filename}\":");
-            eprintln!("{}", e.message());
-        }
-    } else if warn {
-        eprintln!("; WARNING: file \"{full_filename}\" does not exist");
-    }
-}
 
 pub fn read_file(filename: &str) -> Result<MalStr, MalErr> {
-    let mut file = File::open(filename)
-        .map_err(|_| MalErr::unrecoverable(format!("Failed to open file '{filename}'").as_str()))?;
+    let mut file = File::open(filename).map_err(|e| {
+        MalErr::unrecoverable(format!("Failed to open file '{filename}': {e}").as_str())
+    })?;
     let mut content = String::new();
 
-    file.read_to_string(&mut content).map_err(|_| {
-        MalErr::unrecoverable(format!("Failed to read content of '{filename}'").as_str())
+    file.read_to_string(&mut content).map_err(|e| {
+        MalErr::unrecoverable(format!("Failed to read content of '{filename}': {e}").as_str())
     })?;
 
     Ok(content.into())
 }
 
-pub fn load_file(filename: &str, env: &Env) -> MalRet {
-    eval_str(
-        format!("(eval (read-string (str \"(do \" (slurp \"{filename}\") \"\nnil)\")))").as_str(),
-        env,
-    )
-} // WTF this is becoming ever less like rust and more like lisp, did I really completely skip the file reading?
-
-use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
-
-pub fn pre_load(argv: &[String], env: &Env) {
-    eval_str(
-        format!(
-            "(def! *ARGV* '({}))",
-            argv[1..]
-                .iter()
-                .map(|x| "\"".to_string() + x + "\" ")
-                .collect::<String>()
-        )
-        .as_str(),
-        env,
-    )
-    .unwrap();
+/// Read all the forms in `source` into a single "do" form evaluating to nil,
+/// lists keep track of their position in the source, reported as `name`
+pub fn read_source(source: &str, name: &str) -> MalRet {
+    let reader = Reader::new().with_file(name);
+    reader.push(source);
+    let mut forms = vec![MalType::Sym("do".into())];
+    forms.append(&mut read_all(&reader)?);
+    forms.push(MalType::Nil);
+    Ok(MalType::List(forms.into()))
 }
 
-pub fn interactive(env: Env) {
-    const HISTORY: &str = ".mal-history";
-    let home = get_home_path(&env).unwrap();
-    let history = home + "/" + HISTORY;
-    eval_str(format!("(def! MAL_HISTORY \"{history}\")").as_str(), &env).unwrap();
-
-    // Using "Editor" instead of the standard I/O because I hate myself but not this much
-    // TODO: remove unwrap and switch to a better error handling
-    let mut rl = DefaultEditor::new().unwrap();
-    if rl.load_history(&history).is_err() {
-        eprintln!("; Failed to load history");
-    }
-
-    let mut num = 0;
-    let parser = Reader::new();
-    loop {
-        parser.clear();
-        loop {
-            // // Old reader
-            // print!("user> ");
-            // // Flush the prompt to appear before command
-            // let _ = io::stdout().flush();
-
-            // // Read line to compose program input
-            // let mut line = String::new();
-            // io::stdin().read_line(&mut line).unwrap();
-            let line = rl.readline("; mal> ");
-
-            match line {
-                Ok(line) => {
-                    // TODO: should handle this in a different way
-                    rl.add_history_entry(&line).unwrap_or_default();
-                    rl.save_history(&history)
-                        .unwrap_or_else(|e| eprintln!("; WARNING: saving history: {e}"));
-
-                    parser.push(&line);
-
-                    // Perform rep on whole available input
-                    match rep(&parser, &env) {
-                        Ok(output) => output.iter().for_each(|el| {
-                            eprintln!("; [{num}]> {el}");
-                            num += 1;
-                        }),
-                        Err(error) => {
-                            if error.is_recoverable() {
-                                // && line != "\n" {
-                                continue;
-                            }
-                            eprintln!("; [{}]> Error @ {}", num, error.message());
-                            num += 1
-                        }
-                    }
-                    break;
-                }
-                Err(ReadlineError::Interrupted) => {
-                    parser.clear();
-                    continue;
-                }
-                Err(ReadlineError::Eof) => exit(0),
-                Err(err) => {
-                    eprint!("; Error reading lnie: {err:?}");
-                    break;
-                }
-            }
-        }
-    }
+/// Read all the forms in a file, see `read_source`
+pub fn read_forms(filename: &str) -> MalRet {
+    read_source(&read_file(filename)?, filename)
 }
@@ -1,22 +1,14 @@
 use std::rc::Rc;
 
 use crate::types::MalType as M;
-use crate::types::{escape_str, MalType};
-
-fn key_str(val: &str) -> MalType {
-    if val.starts_with('ʞ') {
-        M::Key(val.into())
-    } else {
-        M::Str(val.into())
-    }
-}
+use crate::types::{escape_str, MalMap, MalSet, MalType};
 
 pub fn pr_str(ast: &MalType, print_readably: bool) -> String {
     match ast {
         M::Nil => "NIL".to_string(),
         M::T => "t".to_string(),
         M::Sym(sym) => sym.to_string(),
-        M::Key(sym) => sym[2..].to_string(),
+        M::Key(sym) => sym.to_string(),
         M::Num(val) => val.to_string(),
         M::Str(str) => {
             if print_readably {
@@ -40,23 +32,18 @@ pub fn pr_str(ast: &MalType, print_readably: bool) -> String {
                 .collect::<Vec<String>>()
                 .join(" ")
         ),
-        M::Map(el) => format!(
-            "{{{}}}",
-            el.iter()
-                .map(|sub| [
-                    pr_str(&key_str(sub.0), print_readably),
-                    pr_str(sub.1, print_readably)
-                ]
-                .join(" "))
-                .collect::<Vec<String>>()
-                .join(" ")
-        ),
-        M::Fun(..) => "#<builtin>".to_string(),
+        M::Map(el) => print_map(el, |e| pr_str(e, print_readably)),
+        M::Set(el) => print_set(el, |e| pr_str(e, print_readably)),
+        M::Fun(..) | M::Native(_) => "#<builtin>".to_string(),
+        M::MalFun { is_macro: true, .. } => "#<macro>".to_string(),
         M::MalFun { .. } => "#<function>".to_string(),
         M::Atom(sub) => format!("Atom({})", pr_str(&sub.borrow(), print_readably)),
+        M::Port(port) if port.input => format!("#<input-port {}>", port.name),
+        M::Port(port) => format!("#<output-port {}>", port.name),
+        M::Regex(re) => format!("#<regex {}>", escape_str(re.as_str())),
         M::Ch(c) => {
             if print_readably {
-                format!("#\\{c}")
+                char_str(*c)
             } else {
                 c.to_string()
             }
@@ -64,16 +51,79 @@ pub fn pr_str(ast: &MalType, print_readably: bool) -> String {
     }
 }
 
+// Entries are sorted by key, so that the same map is always printed the same
+fn print_map(map: &MalMap, print: impl Fn(&MalType) -> String) -> String {
+    let mut entries = map
+        .iter()
+        .map(|(k, v)| (print(k), print(v)))
+        .collect::<Vec<(String, String)>>();
+    entries.sort();
+    format!(
+        "{{{}}}",
+        entries
+            .into_iter()
+            .map(|(k, v)| format!("{k} {v}"))
+            .collect::<Vec<String>>()
+            .join(" ")
+    )
+}
+
+fn print_set(set: &MalSet, print: impl Fn(&MalType) -> String) -> String {
+    let mut items = set.iter().map(print).collect::<Vec<String>>();
+    items.sort();
+    format!("#{{{}}}", items.join(" "))
+}
+
+fn char_str(c: char) -> String {
+    match c {
+        '\n' => "\\newline".to_string(),
+        '\r' => "\\return".to_string(),
+        ' ' => "\\space".to_string(),
+        '\t' => "\\tab".to_string(),
+        c if c.is_control() => format!("\\u{:04X}", c as u32),
+        c => format!("\\{c}"),
+    }
+}
+
+/// Print a value as EDN, reading the output back gives an equal value for
+/// everything but functions, which are printed as unreadable forms
+pub fn pr_edn(ast: &MalType) -> String {
+    match ast {
+        M::Nil => "nil".to_string(),
+        M::T => "true".to_string(),
+        M::Num(val) if !val.is_exact() && !val.to_f64().is_finite() => {
+            let val = val.to_f64();
+            if val.is_nan() {
+                "##NaN".to_string()
+            } else if val > 0.0 {
+                "##Inf".to_string()
+            } else {
+                "##-Inf".to_string()
+            }
+        }
+        M::List(el) => format!(
+            "({})",
+            el.iter().map(pr_edn).collect::<Vec<String>>().join(" ")
+        ),
+        M::Vector(el) => format!(
+            "[{}]",
+            el.iter().map(pr_edn).collect::<Vec<String>>().join(" ")
+        ),
+        M::Map(el) => print_map(el, pr_edn),
+        M::Set(el) => print_set(el, pr_edn),
+        M::Atom(sub) => format!("#mal/atom {}", pr_edn(&sub.borrow())),
+        _ => pr_str(ast, true),
+    }
+}
+
 pub fn prt(ast: &MalType) -> String {
     pr_str(ast, true)
 }
 
-pub fn print_malfun(sym: &str, params: Rc<MalType>, ast: Rc<MalType>) {
-    println!("; {}\t[function]: {}", sym, prt(&params));
-    ast.as_ref()
-        .if_list()
-        .unwrap_or(&[])
-        .iter()
-        .for_each(|el| println!(";   {}", pr_str(el, true)));
-    println!();
+pub fn print_malfun(sym: &str, kind: &str, params: Rc<MalType>, ast: Rc<MalType>) -> String {
+    let mut lines = vec![format!("; {}\t[{}]: {}", sym, kind, prt(&params))];
+    if let Ok(body) = ast.if_list() {
+        lines.extend(body.iter().map(|el| format!(";   {}", pr_str(el, true))));
+    }
+    lines.join("\n")
 }
@@ -10,32 +10,91 @@ use regex::Regex;
 
 pub struct Reader {
     tokens: RefCell<Vec<String>>,
+    locs: RefCell<Vec<Option<Rc<Loc>>>>,
     ptr: Cell<usize>,
+    // Positions are only tracked when reading from a named source
+    file: Option<MalStr>,
+    lines: Cell<usize>,
+    // Only data is accepted, no quoting shorthands
+    edn: bool,
 }
 
-type Tokens = Vec<String>;
+// Each token is paired with its byte offset in the input
+type Tokens = Vec<(String, usize)>;
 
 // DONE: instead of panic on missing ")" try implementing a multi line parsing
 // Status on return should always be The last element of the last opened lists
 // (append to the "last" list) while traversing
+impl Default for Reader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Reader {
     pub fn new() -> Reader {
         Reader {
             tokens: RefCell::new(Vec::new()),
+            locs: RefCell::new(Vec::new()),
             ptr: Cell::new(0),
+            file: None,
+            lines: Cell::new(0),
+            edn: false,
         }
     }
 
+    /// Record the position of the forms read, reporting them as part of `file`
+    pub fn with_file(mut self, file: &str) -> Reader {
+        self.file = Some(file.into());
+        self
+    }
+
+    /// Read EDN data, reader shorthands for quoting and deref are rejected
+    pub fn edn(mut self) -> Reader {
+        self.edn = true;
+        self
+    }
+
     pub fn push(&self, input: &str) -> &Self {
         self.ptr.set(0);
         // reset the state of the parser and push the additional strings
-        self.tokens.borrow_mut().append(&mut tokenize(input));
+        let tokens = tokenize(input);
+        let mut locs = self.locs.borrow_mut();
+        match &self.file {
+            Some(file) => {
+                let (mut line, mut col, mut offset) = (self.lines.get() + 1, 1, 0);
+                for (_, start) in &tokens {
+                    for c in input[offset..*start].chars() {
+                        if c == '\n' {
+                            line += 1;
+                            col = 1;
+                        } else {
+                            col += 1;
+                        }
+                    }
+                    offset = *start;
+                    locs.push(Some(Rc::new(Loc {
+                        file: file.clone(),
+                        line,
+                        col,
+                    })));
+                }
+            }
+            None => locs.extend(tokens.iter().map(|_| None)),
+        }
+        self.lines
+            .set(self.lines.get() + input.matches('\n').count() + 1);
+        self.tokens
+            .borrow_mut()
+            .extend(tokens.into_iter().map(|(token, _)| token));
         self
     }
 
     pub fn clear(&self) {
         self.ptr.set(0);
+        self.lines.set(0);
         *self.tokens.borrow_mut() = Vec::new();
+        *self.locs.borrow_mut() = Vec::new();
     }
 
     // May be improved
@@ -47,6 +106,11 @@ impl Reader {
             .cloned()
     }
 
+    /// Returns the position of the token at the current position, if known
+    fn loc(&self) -> Option<Rc<Loc>> {
+        self.locs.borrow().get(self.ptr.get()).cloned().flatten()
+    }
+
     /// Returns the token at the current position
     fn peek(&self) -> Result<String, MalErr> {
         self.get_token(self.ptr.get())
@@ -69,18 +133,23 @@ impl Reader {
     /// NOTE: `read_list` calls `read_form` -> enable recursion
     /// (lists can contains other lists)
     fn read_list(&self, terminator: &str) -> MalRet {
-        self.next()?;
+        let loc = self.loc();
+        let open = self.next()?;
 
         let mut vector = Vec::new();
 
-        while self.peek()? != terminator {
-            vector.push(self.read_form()?)
+        // Missing terminators are reported where the collection was opened
+        self.skip_discarded()?;
+        while self.peek().map_err(|e| e.at(loc.as_ref()))? != terminator {
+            vector.push(self.read_form()?);
+            self.skip_discarded()?;
         }
         self.next()?;
 
         match terminator {
-            ")" => Ok(List(vector.into())),
-            "]" => Ok(Vector(vector.into())),
+            ")" => Ok(List(MalArgs::from(vector).with_loc(loc))),
+            "]" => Ok(Vector(MalArgs::from(vector).with_loc(loc))),
+            "}" if open == "#{" => Ok(Set(vector.into_iter().collect())),
             "}" => make_map(vector.into()),
             t => Err(MalErr::unrecoverable(
                 format!("Unknown collection terminator: {t}").as_str(),
@@ -90,21 +159,31 @@ impl Reader {
 
     /// Read atomic token and return appropriate scalar ()
     fn read_atom(&self) -> MalRet {
+        let loc = self.loc();
+        self.read_token().map_err(|e| e.at(loc.as_ref()))
+    }
+
+    fn read_token(&self) -> MalRet {
         match &self.next()?[..] {
             ")" | "]" | "}" => Err(MalErr::unrecoverable("Missing open parenthesis")),
-            "t" => Ok(T),
-            "nil" => Ok(Nil),
+            // true and false are literals in every mode, as printed by pr-str,
+            // they used to be symbols bound in core.mal and can't be rebound
+            "t" | "true" => Ok(T),
+            "nil" | "false" => Ok(Nil),
+            "##Inf" => Ok(Num(Frac::inexact(f64::INFINITY))),
+            "##-Inf" => Ok(Num(Frac::inexact(f64::NEG_INFINITY))),
+            "##NaN" => Ok(Num(Frac::inexact(f64::NAN))),
+            "#" => Err(MalErr::unrecoverable("Unsupported dispatch form: #")),
+            tk if tk.starts_with("#<") => Err(MalErr::unrecoverable(
+                format!("Unreadable form: {tk}").as_str(),
+            )),
+            tk if tk.starts_with('\\') => read_char(tk).map(Ch),
             tk => {
-                if Regex::new(r"^[-\+]?[0-9]+(/[0-9]+)?$")
+                if Regex::new(r"^[-\+]?([0-9]+(/[0-9]+)?|([0-9]+\.[0-9]*|\.[0-9]+|[0-9]+)([eE][-\+]?[0-9]+)?)$")
                     .unwrap()
                     .is_match(tk)
                 {
-                    return match Frac::from_str(tk) {
-                        Some(v) => Ok(Num(v)),
-                        None => Err(MalErr::unrecoverable(
-                            format!("Cannot parse {tk} as a number").as_str(),
-                        )),
-                    };
+                    return tk.parse::<Frac>().map(Num);
                 }
                 if tk.starts_with('\"') {
                     if tk.len() > 1 && tk.ends_with('\"') {
@@ -115,66 +194,164 @@ impl Reader {
                     ));
                 }
                 if tk.starts_with(':') {
-                    return Ok(Key(format!("ʞ{tk}").into()));
+                    return Ok(Key(tk.into()));
                 }
                 Ok(Sym(tk.into()))
             }
         }
     }
 
+    /// Read a tagged literal, "#tag form", only tags known to the reader are
+    /// accepted: atoms, and EDN instants and UUIDs that are kept as strings
+    fn read_tagged(&self) -> MalRet {
+        let loc = self.loc();
+        let tag = self.next()?;
+        match (&tag[1..], self.read_form()?) {
+            ("mal/atom", form) => Ok(Atom(Rc::new(RefCell::new(form)))),
+            ("inst" | "uuid", form @ Str(_)) => Ok(form),
+            (tag, _) => Err(MalErr::unrecoverable(
+                format!("No reader function for tag {tag}").as_str(),
+            )
+            .at(loc.as_ref())),
+        }
+    }
+
+    /// Skip the forms marked with "#_"
+    fn skip_discarded(&self) -> Result<(), MalErr> {
+        while self.peek().is_ok_and(|tk| tk == "#_") {
+            self.next()?;
+            self.read_form()?;
+        }
+        Ok(())
+    }
+
     /// Peek at the first token in reader
     ///
     /// Switch on the first character
     /// "(" -> call `read_list`
     /// otherwise  -> call `read_atom`
     fn read_form(&self) -> MalRet {
+        self.skip_discarded()?;
         // String slice containing the whole string
         match &self.peek()?[..] {
+            tk @ ("'" | "@" | "`" | "~" | "~@") if self.edn => Err(MalErr::unrecoverable(
+                format!("{tk} is not valid in EDN").as_str(),
+            )
+            .at(self.loc().as_ref())),
             // Consume "(" and parse list
             "(" => self.read_list(")"),
             "[" => self.read_list("]"),
-            "{" => self.read_list("}"),
+            "{" | "#{" => self.read_list("}"),
             // Ugly quote transformation for quote expansion
             "'" => {
+                let loc = self.loc();
                 self.next()?;
-                Ok(List(Rc::new([
-                    MalType::Sym("quote".into()),
-                    self.read_form()?,
-                ])))
+                Ok(List(
+                    MalArgs::from([MalType::Sym("quote".into()), self.read_form()?]).with_loc(loc),
+                ))
             }
             "@" => {
+                let loc = self.loc();
+                self.next()?;
+                Ok(List(
+                    MalArgs::from([MalType::Sym("deref".into()), self.read_form()?]).with_loc(loc),
+                ))
+            }
+            "`" => {
+                let loc = self.loc();
+                self.next()?;
+                Ok(List(
+                    MalArgs::from([MalType::Sym("quasiquote".into()), self.read_form()?])
+                        .with_loc(loc),
+                ))
+            }
+            "~" => {
+                let loc = self.loc();
+                self.next()?;
+                Ok(List(
+                    MalArgs::from([MalType::Sym("unquote".into()), self.read_form()?])
+                        .with_loc(loc),
+                ))
+            }
+            "~@" => {
+                let loc = self.loc();
                 self.next()?;
-                Ok(List(Rc::new([
-                    MalType::Sym("deref".into()),
-                    self.read_form()?,
-                ])))
+                Ok(List(
+                    MalArgs::from([MalType::Sym("splice-unquote".into()), self.read_form()?])
+                        .with_loc(loc),
+                ))
             }
+            tk if is_tag(tk) => self.read_tagged(),
             _ => self.read_atom(),
         }
     }
 }
 
+// Namespaced tags, plus the ones defined by EDN, other symbols starting with
+// "#" are left alone
+fn is_tag(tk: &str) -> bool {
+    tk.starts_with('#')
+        && !tk.starts_with("##")
+        && (tk.contains('/') || matches!(tk, "#inst" | "#uuid"))
+}
+
+/// Read a character literal: "\\c", "\\newline" or "\\u00e8"
+fn read_char(tk: &str) -> Result<char, MalErr> {
+    let name = &tk[1..];
+    let mut chars = name.chars();
+    match (chars.next(), chars.next(), name) {
+        (Some(c), None, _) => Some(c),
+        (_, _, "newline") => Some('\n'),
+        (_, _, "return") => Some('\r'),
+        (_, _, "space") => Some(' '),
+        (_, _, "tab") => Some('\t'),
+        (_, _, "formfeed") => Some('\u{000C}'),
+        (_, _, "backspace") => Some('\u{0008}'),
+        (Some('u'), _, _) if name.len() == 5 => u32::from_str_radix(&name[1..], 16)
+            .ok()
+            .and_then(char::from_u32),
+        _ => None,
+    }
+    .ok_or(MalErr::unrecoverable(
+        format!("Invalid character literal: {tk}").as_str(),
+    ))
+}
+
 /// Call `tokenize` on a string
 /// Create anew Reader with the tokens
 /// Call read_from with the reader instance
 pub fn read_str(reader: &Reader) -> MalRet {
     let mut ret = Nil;
+    reader.skip_discarded()?;
     while !reader.ended() {
         ret = reader.read_form()?;
+        reader.skip_discarded()?;
     }
     Ok(ret)
 }
 
+/// Read all the remaining forms in the reader
+pub fn read_all(reader: &Reader) -> Result<Vec<MalType>, MalErr> {
+    let mut forms = Vec::new();
+    reader.skip_discarded()?;
+    while !reader.ended() {
+        forms.push(reader.read_form()?);
+        reader.skip_discarded()?;
+    }
+    Ok(forms)
+}
+
 /// Read a string and return a list of tokens in it (following regex in README)
 // Add error handling for strings that are not terminated
 fn tokenize(input: &str) -> Tokens {
     let tokens =
-        Regex::new(r#"[\s,]*(~@|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|[^\s\[\]{}('"`,;)]*)"#)
+        Regex::new(r#"[\s,]*(~@|#_|#\{|[\[\]{}()'`~^@]|"(?:\\.|[^\\"])*"?|;.*|\\.[^\s\[\]{}('"`,;)]*|[^\s\[\]{}('"`,;)]*)"#)
             .unwrap()
             .captures_iter(input)
-            .map(|e| e[1].to_string())
-            .filter(|e| !(e.is_empty() || e.starts_with(';')))
-            .collect::<Vec<String>>();
+            .map(|e| e.get(1).unwrap())
+            .filter(|e| !(e.is_empty() || e.as_str().starts_with(';')))
+            .map(|e| (e.as_str().to_string(), e.start()))
+            .collect::<Tokens>();
     tokens
 }
 
@@ -185,10 +362,11 @@ fn tokenize(input: &str) -> Tokens {
 #[cfg(test)]
 mod tests {
     use std::borrow::Borrow;
+    use std::cell::RefCell;
 
     use crate::{
         reader::read_str,
-        types::{Frac, MalMap, MalType as M},
+        types::{Frac, MalType as M},
     };
 
     use super::{tokenize, Reader};
@@ -209,7 +387,10 @@ mod tests {
     #[test]
     fn do_tokenize() {
         assert_eq!(
-            tokenize("()[]{} \"str\" :key sym 1 ; comment"),
+            tokenize("()[]{} \"str\" :key sym 1 ; comment")
+                .into_iter()
+                .map(|(token, _)| token)
+                .collect::<Vec<_>>(),
             vec!["(", ")", "[", "]", "{", "}", "\"str\"", ":key", "sym", "1"]
         );
     }
@@ -289,7 +470,7 @@ mod tests {
             matches!(r.read_atom(), Ok(x) if matches!(x.clone(), M::Str(v) if matches!(v.borrow(), "s")))
         );
         assert!(
-            matches!(r.read_atom(), Ok(x) if matches!(x.clone(), M::Key(v) if matches!(v.borrow(), "ʞ:a")))
+            matches!(r.read_atom(), Ok(x) if matches!(x.clone(), M::Key(v) if matches!(v.borrow(), ":a")))
         );
         assert!(matches!(r.read_atom(), Err(e) if !e.is_recoverable()));
         assert!(matches!(r.read_atom(), Err(e) if !e.is_recoverable()));
@@ -325,26 +506,88 @@ mod tests {
         // Test map
         r.push("{\"i\" 1 \"s\" \"str\" \"t\" t \"n\" nil :s :sym}");
         let t = match read_str(&r) {
-            Ok(x) => match x {
-                M::Map(x) => x,
-                _ => {
-                    assert!(false);
-                    MalMap::new()
-                }
-            },
-            _ => {
-                assert!(false);
-                MalMap::new()
-            }
+            Ok(M::Map(x)) => x,
+            _ => panic!("Expected a map"),
         };
-        assert!(matches!(t.get("n"),   Some(x) if matches!(&x, M::Nil)));
-        assert!(matches!(t.get("t"),   Some(x) if matches!(&x, M::T)));
-        assert!(matches!(t.get("i"),   Some(x) if matches!(&x, M::Num(v) if v.int() == 1)));
+        let get = |k: &str| t.get(&M::Str(k.into()));
+        assert!(matches!(get("n"), Some(x) if matches!(&x, M::Nil)));
+        assert!(matches!(get("t"), Some(x) if matches!(&x, M::T)));
+        assert!(matches!(get("i"), Some(x) if matches!(&x, M::Num(v) if v.int() == 1)));
         assert!(
-            matches!(t.get("s"),   Some(x) if matches!(&x, M::Str(v) if matches!(v.borrow(), "str")))
+            matches!(get("s"), Some(x) if matches!(&x, M::Str(v) if matches!(v.borrow(), "str")))
         );
         assert!(
-            matches!(t.get("ʞ:s"), Some(x) if matches!(&x, M::Key(v) if matches!(v.borrow(), "ʞ:sym")))
+            matches!(t.get(&M::Key(":s".into())), Some(x) if matches!(&x, M::Key(v) if matches!(v.borrow(), ":sym")))
         );
     }
+
+    #[test]
+    fn quote_expansion() {
+        let r = Reader::new();
+        for (input, expected) in [
+            ("'a", "quote"),
+            ("@a", "deref"),
+            ("`a", "quasiquote"),
+            ("~a", "unquote"),
+            ("~@a", "splice-unquote"),
+        ] {
+            r.clear();
+            r.push(input);
+            assert!(matches!(
+                read_str(&r), Ok(M::List(list))
+                if list.len() == 2
+                && matches!(&list[0], M::Sym(v) if v.as_ref() == expected)
+                && matches!(&list[1], M::Sym(v) if v.as_ref() == "a")));
+        }
+    }
+
+    #[test]
+    fn locations() {
+        let r = Reader::new().with_file("test.mal");
+        r.push("(a\n  (b \"c\"))");
+        assert!(matches!(
+            read_str(&r), Ok(M::List(list))
+            if matches!(list.loc(), Some(l) if (l.line, l.col) == (1, 1))
+            && matches!(&list[1], M::List(l)
+                if matches!(l.loc(), Some(l) if (l.line, l.col) == (2, 3)))));
+
+        // Errors point to the unclosed collection or the unterminated string
+        for (input, line, col) in [("(a\n  (b c)", 1, 1), ("(a\n  \"b)", 2, 3), ("a\n)", 2, 1)] {
+            let r = Reader::new().with_file("test.mal");
+            r.push(input);
+            assert!(matches!(
+                read_str(&r), Err(e)
+                if matches!(e.loc(), Some(l) if l.file.as_ref() == "test.mal" && (l.line, l.col) == (line, col))));
+        }
+
+        // Without a file name positions are not tracked
+        let r = Reader::new();
+        r.push("(a)");
+        assert!(matches!(read_str(&r), Ok(M::List(list)) if list.loc().is_none()));
+    }
+
+    #[test]
+    fn edn() {
+        let r = Reader::new();
+        r.push("[\\a \\( \\space \\u00e8 #_ignored true false ##-Inf #mal/atom 1 #r]");
+        assert!(matches!(
+            read_str(&r), Ok(M::Vector(v))
+            if matches!(&v.to_vec()[..], [M::Ch('a'), M::Ch('('), M::Ch(' '), M::Ch('è'), M::T, M::Nil,
+                                 M::Num(inf), M::Atom(a), M::Sym(r)]
+                if inf.to_f64() == f64::NEG_INFINITY
+                && matches!(&*RefCell::borrow(a), M::Num(v) if v.int() == 1)
+                && r.as_ref() == "#r")));
+
+        // Unknown tags, functions and invalid chars are errors
+        for input in ["#my/tag 1", "#<function>", "\\invalid"] {
+            let r = Reader::new();
+            r.push(input);
+            assert!(matches!(read_str(&r), Err(e) if !e.is_recoverable()));
+        }
+
+        // EDN data has no quoting shorthands
+        let r = Reader::new().edn();
+        r.push("'a");
+        assert!(matches!(read_str(&r), Err(e) if !e.is_recoverable()));
+    }
 }
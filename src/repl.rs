@@ -0,0 +1,123 @@
+// Interactive side of the binary: config, banner and the read-eval-print loop
+
+use rust_mal::{Interpreter, MalErr, MalType, Reader};
+use std::path::Path;
+use std::process::exit;
+
+/// Print the error followed by the calls it went through, or quit the
+/// program if the error is a request to exit
+pub fn report(error: &MalErr) {
+    if let Some(code) = error.exit_code() {
+        exit(code);
+    }
+    eprintln!("{error}");
+    for frame in error.trace() {
+        eprintln!(";   at {frame}");
+    }
+}
+
+pub fn print_banner(mal: &Interpreter) {
+    let _ = mal.eval_str("(prn BANNER)");
+}
+
+fn get_home_path(mal: &Interpreter) -> String {
+    match mal.get("MAL_HOME") {
+        Some(MalType::Str(home)) => home.to_string(),
+        _ => "".to_string(),
+    }
+}
+
+pub fn load_home_file(filename: &str, mal: &Interpreter, warn: bool) {
+    let full_filename = get_home_path(mal) + "/" + filename;
+
+    if Path::new(&full_filename).exists() {
+        if let Err(e) = mal.load_file(&full_filename) {
+            eprintln!("; reading \"{full_filename}\":");
+            report(&e);
+        }
+    } else if warn {
+        eprintln!("; WARNING: file \"{full_filename}\" does not exist");
+    }
+}
+
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+
+pub fn pre_load(argv: &[String], mal: &Interpreter) {
+    let argv = argv[1..].iter().map(|x| MalType::Str(x.as_str().into()));
+    mal.define("*ARGV*", MalType::List(argv.collect()));
+}
+
+pub fn interactive(mal: Interpreter) {
+    const HISTORY: &str = ".mal-history";
+    let home = get_home_path(&mal);
+    let history = home + "/" + HISTORY;
+    mal.define("MAL_HISTORY", MalType::Str(history.as_str().into()));
+
+    // Using "Editor" instead of the standard I/O because I hate myself but not this much
+    // TODO: remove unwrap and switch to a better error handling
+    let mut rl = DefaultEditor::new().unwrap();
+    if rl.load_history(&history).is_err() {
+        eprintln!("; Failed to load history");
+    }
+
+    let mut num = 0;
+    let parser = Reader::new();
+    loop {
+        parser.clear();
+        loop {
+            // // Old reader
+            // print!("user> ");
+            // // Flush the prompt to appear before command
+            // let _ = io::stdout().flush();
+
+            // // Read line to compose program input
+            // let mut line = String::new();
+            // io::stdin().read_line(&mut line).unwrap();
+            let line = rl.readline("; mal> ");
+
+            match line {
+                Ok(line) => {
+                    // TODO: should handle this in a different way
+                    rl.add_history_entry(&line).unwrap_or_default();
+                    rl.save_history(&history)
+                        .unwrap_or_else(|e| eprintln!("; WARNING: saving history: {e}"));
+
+                    parser.push(&line);
+
+                    // Perform rep on whole available input
+                    match mal.rep(&parser) {
+                        Ok(output) => output.iter().for_each(|el| {
+                            eprintln!("; [{num}]> {el}");
+                            num += 1;
+                        }),
+                        Err(error) => {
+                            if error.is_recoverable() {
+                                // && line != "\n" {
+                                continue;
+                            }
+                            if let Some(code) = error.exit_code() {
+                                exit(code);
+                            }
+                            eprintln!("; [{num}]> Error @ {error}");
+                            for frame in error.trace() {
+                                eprintln!(";   at {frame}");
+                            }
+                            num += 1
+                        }
+                    }
+                    break;
+                }
+                Err(ReadlineError::Interrupted) => {
+                    parser.clear();
+                    continue;
+                }
+                Err(ReadlineError::Eof) => exit(0),
+                Err(err) => {
+                    eprint!("; Error reading lnie: {err:?}");
+                    break;
+                }
+            }
+        }
+    }
+}
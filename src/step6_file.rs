@@ -7,7 +7,7 @@
 // FIXME: (?) multiple sentences per line, only last is kept
 
 use crate::env::Env;
-use crate::eval::eval;
+use crate::eval::{eval, is_help_form};
 use crate::printer::pr_str;
 use crate::reader::{read_str, Reader};
 use crate::types::{MalErr, MalRet, MalType};
@@ -15,13 +15,13 @@ use crate::types::{MalErr, MalRet, MalType};
 #[allow(non_snake_case)]
 /// Read input and generate an ast
 fn READ(input: &Reader) -> MalRet {
-    read_str(input).map_err(|err| MalErr::new(format!("READ: {}", err.message()), err.severity()))
+    read_str(input).map_err(|err| err.prefixed("READ"))
 }
 
 #[allow(non_snake_case)]
 /// Evaluate the generated ast
 fn EVAL(ast: MalType, env: Env) -> MalRet {
-    eval(&ast, env).map_err(|err| MalErr::new(format!("EVAL: {}", err.message()), err.severity()))
+    eval(&ast, env).map_err(|err| err.prefixed("EVAL"))
 }
 
 #[allow(non_snake_case)]
@@ -34,8 +34,11 @@ pub fn rep(reader: &Reader, env: &Env) -> Result<Vec<String>, MalErr> {
     let mut ret_str = Vec::new();
     while !reader.ended() {
         let ast = READ(reader)?;
-        let out = EVAL(ast, env.clone())?;
-        ret_str.push(PRINT(out));
+        let raw = is_help_form(&ast);
+        match EVAL(ast, env.clone())? {
+            MalType::Str(text) if raw => ret_str.push(text.to_string()),
+            out => ret_str.push(PRINT(out)),
+        }
     }
     Ok(ret_str)
 }
@@ -1,74 +1,168 @@
 use crate::env::{car_cdr, Env};
+use crate::mal_port::Port;
+use crate::mal_regex::Pattern;
+use archery::RcK;
+use num_bigint::BigInt;
+use num_integer::Integer;
+use num_rational::BigRational;
+use num_traits::{One, Signed, ToPrimitive, Zero};
+use rpds::{HashTrieMap, HashTrieSet, List};
 use std::{
     cell::RefCell,
     cmp::Ordering,
-    collections::HashMap,
-    ops::{Add, Div, Mul, Sub},
+    collections::hash_map::DefaultHasher,
+    hash::{Hash, Hasher},
+    iter::{self, Skip},
+    mem,
+    ops::{Add, Div, Index, Mul, Sub},
     rc::Rc,
+    str::FromStr,
 };
 
 pub type MalStr = Rc<str>;
-pub type MalArgs = Rc<[MalType]>;
-pub type MalMap = HashMap<MalStr, MalType>;
+pub type MalMap = HashTrieMap<MalType, MalType>;
+pub type MalSet = HashTrieSet<MalType>;
 pub type MalRet = Result<MalType, MalErr>;
 
-#[derive(Clone, Copy)]
+#[derive(Clone)]
+enum Repr {
+    // Fast path, used whenever the reduced fraction fits in machine words
+    Small { num: isize, den: usize },
+    // Arbitrary precision, used only when the value does not fit in Small
+    Big(Rc<BigRational>),
+    // Floating point, the result of any operation involving an inexact value
+    Inexact(f64),
+}
+
+// Exact fractions are always reduced to the lowest terms, so that each value
+// has a single representation
+#[derive(Clone)]
 pub struct Frac {
-    num: isize,
-    den: usize,
+    repr: Repr,
+}
+
+use Repr::{Big, Inexact, Small};
+
+// Operation on numerators and denominators of two fractions, returns None on
+// overflow
+type SmallOp = fn(i128, i128, i128, i128) -> Option<(i128, i128)>;
+
+// Apply the operation on the small representation if both values have it,
+// None if either does not or if the result overflows
+fn small_op(a: &Frac, b: &Frac, op: SmallOp) -> Option<Frac> {
+    match (&a.repr, &b.repr) {
+        (Small { num: an, den: ad }, Small { num: bn, den: bd }) => {
+            let (num, den) = op(*an as i128, *ad as i128, *bn as i128, *bd as i128)?;
+            Frac::from_i128(num, den)
+        }
+        _ => None,
+    }
+}
+
+// Apply the operation on floats if any of the values is inexact, None if both
+// are exact
+fn inexact_op(a: &Frac, b: &Frac, op: fn(f64, f64) -> f64) -> Option<Frac> {
+    match (&a.repr, &b.repr) {
+        (Inexact(_), _) | (_, Inexact(_)) => Some(Frac::inexact(op(a.to_f64(), b.to_f64()))),
+        _ => None,
+    }
 }
 
 impl Add for Frac {
     type Output = Self;
     fn add(self, other: Self) -> Self {
-        Self {
-            num: self.num * other.den as isize + other.num * self.den as isize,
-            den: self.den * other.den,
-        }
+        inexact_op(&self, &other, |a, b| a + b)
+            .or_else(|| {
+                small_op(&self, &other, |an, ad, bn, bd| {
+                    Some((
+                        an.checked_mul(bd)?.checked_add(bn.checked_mul(ad)?)?,
+                        ad.checked_mul(bd)?,
+                    ))
+                })
+            })
+            .unwrap_or_else(|| Self::from_big(self.big() + other.big()))
     }
 }
 
 impl Sub for Frac {
     type Output = Self;
     fn sub(self, other: Self) -> Self {
-        Self {
-            num: self.num * other.den as isize - other.num * self.den as isize,
-            den: self.den * other.den,
-        }
+        inexact_op(&self, &other, |a, b| a - b)
+            .or_else(|| {
+                small_op(&self, &other, |an, ad, bn, bd| {
+                    Some((
+                        an.checked_mul(bd)?.checked_sub(bn.checked_mul(ad)?)?,
+                        ad.checked_mul(bd)?,
+                    ))
+                })
+            })
+            .unwrap_or_else(|| Self::from_big(self.big() - other.big()))
     }
 }
 
 impl Mul for Frac {
     type Output = Self;
     fn mul(self, other: Self) -> Self {
-        Self {
-            num: self.num * other.num,
-            den: self.den * other.den,
-        }
+        inexact_op(&self, &other, |a, b| a * b)
+            .or_else(|| {
+                small_op(&self, &other, |an, ad, bn, bd| {
+                    Some((an.checked_mul(bn)?, ad.checked_mul(bd)?))
+                })
+            })
+            .unwrap_or_else(|| Self::from_big(self.big() * other.big()))
     }
 }
 
 impl Div for Frac {
     type Output = Self;
     fn div(self, other: Frac) -> Self {
-        let other_sign = other.num.signum();
-        Self {
-            num: self.num * other.den as isize * other_sign,
-            den: self.den * other.num.unsigned_abs(),
-        }
+        inexact_op(&self, &other, |a, b| a / b)
+            .or_else(|| {
+                small_op(&self, &other, |an, ad, bn, bd| {
+                    Some((an.checked_mul(bd)?, ad.checked_mul(bn)?))
+                })
+            })
+            .unwrap_or_else(|| Self::from_big(self.big() / other.big()))
     }
 }
 
 impl PartialOrd for Frac {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some((self.num * other.den as isize).cmp(&(other.num * self.den as isize)))
+        match (&self.repr, &other.repr) {
+            // Products of isize and usize always fit in i128
+            (Small { num: an, den: ad }, Small { num: bn, den: bd }) => {
+                Some((*an as i128 * *bd as i128).cmp(&(*bn as i128 * *ad as i128)))
+            }
+            (Inexact(a), Inexact(b)) => a.partial_cmp(b),
+            // Compare mixed values exactly, unless one is infinite or NaN
+            _ => match (self.to_exact(), other.to_exact()) {
+                (Some(a), Some(b)) => Some(a.big().cmp(&b.big())),
+                _ => self.to_f64().partial_cmp(&other.to_f64()),
+            },
+        }
     }
 }
 
 impl PartialEq for Frac {
     fn eq(&self, other: &Self) -> bool {
-        (!((self.num < 0) ^ (other.num < 0)))
-            && self.num.unsigned_abs() * other.den == other.num.unsigned_abs() * self.den
+        // Exact values are always reduced and stored as Small when possible
+        match (&self.repr, &other.repr) {
+            (Small { num: an, den: ad }, Small { num: bn, den: bd }) => an == bn && ad == bd,
+            (Big(a), Big(b)) => a == b,
+            (Inexact(_), _) | (_, Inexact(_)) => self.partial_cmp(other) == Some(Ordering::Equal),
+            _ => false,
+        }
+    }
+}
+
+// Equal values convert to the same float, exact or not
+impl Hash for Frac {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self.to_f64() {
+            0.0 => 0.0f64.to_bits().hash(state),
+            val if val.is_nan() => f64::NAN.to_bits().hash(state),
+            val => val.to_bits().hash(state),
+        }
     }
 }
 
@@ -76,95 +170,535 @@ use std::fmt;
 
 impl fmt::Display for Frac {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        if self.den == 1 {
-            write!(f, "{}", self.num)
-        } else {
-            write!(f, "{}/{}", self.num, self.den)
+        match &self.repr {
+            Small { num, den: 1 } => write!(f, "{num}"),
+            Small { num, den } => write!(f, "{num}/{den}"),
+            Big(val) if val.is_integer() => write!(f, "{}", val.numer()),
+            Big(val) => write!(f, "{}/{}", val.numer(), val.denom()),
+            // Debug formatting always shows the decimal point or the exponent
+            Inexact(val) => write!(f, "{val:?}"),
         }
     }
 }
 
-impl Frac {
-    //pub fn frac(num: isize, den: usize) -> Self {
-    //    Self { num, den }
-    //}
+/// Simplest fraction in the closed interval, the one with the smallest
+/// denominator (and numerator)
+fn simplest_between(lo: BigRational, hi: BigRational) -> BigRational {
+    if hi.is_negative() {
+        return -simplest_between(-hi, -lo);
+    }
+    if !lo.is_positive() {
+        return BigRational::zero();
+    }
+    let fl = lo.floor();
+    if fl == lo {
+        return lo;
+    }
+    if fl.clone() + BigRational::one() <= hi {
+        return fl + BigRational::one();
+    }
+    // Continued fraction expansion of the interval
+    let lo_rest = (lo - fl.clone()).recip();
+    let hi_rest = (hi - fl.clone()).recip();
+    fl + simplest_between(hi_rest, lo_rest).recip()
+}
 
+impl Frac {
     pub fn num(num: isize) -> Self {
-        Self { num, den: 1 }
+        Self {
+            repr: Small { num, den: 1 },
+        }
     }
 
-    pub fn exact_zero(&self) -> bool {
-        self.num == 0
+    pub fn inexact(val: f64) -> Self {
+        Self { repr: Inexact(val) }
     }
 
-    pub fn get_num(&self) -> isize {
-        self.num
+    pub fn integer(val: i128) -> Self {
+        Self::from_i128(val, 1)
+            .unwrap_or_else(|| Self::from_big(BigRational::from(BigInt::from(val))))
     }
 
-    pub fn get_den(&self) -> usize {
-        self.den
+    /// Reduce the fraction and store it as Small, None if it does not fit
+    fn from_i128(num: i128, den: i128) -> Option<Self> {
+        let gcd = num.gcd(&den);
+        let sign = den.signum();
+        let (num, den) = (num / gcd * sign, den / gcd * sign);
+        Some(Self {
+            repr: Small {
+                num: num.try_into().ok()?,
+                den: den.try_into().ok()?,
+            },
+        })
     }
 
-    fn _gcd(&self) -> usize {
-        let mut t: usize;
-        let mut a = self.num.unsigned_abs();
-        let mut b = self.den;
-        while b > 0 {
-            t = b;
-            b = a % b;
-            a = t;
+    /// Store an already reduced fraction, as Small if it fits
+    fn from_big(val: BigRational) -> Self {
+        match (val.numer().to_isize(), val.denom().to_usize()) {
+            (Some(num), Some(den)) => Self {
+                repr: Small { num, den },
+            },
+            _ => Self {
+                repr: Big(Rc::new(val)),
+            },
         }
-        a
     }
 
-    // Ideally builtin functions ( + - * / ) can operate with the values in any
-    // form without problems, occasional simplification is done for numeric
-    // stability, ensure to simplify after insert and before using any funcition
-    // (floor, ceil, etc.)
-    pub fn simplify(&self) -> Frac {
-        // Euclid's algorithm to reduce fraction
-        // TODO: (decide if implementing this automathically once fraction
-        //          numbers become bigger than specified)
-        let gcd = self._gcd();
-        Frac {
-            num: self.num / gcd as isize,
-            den: self.den / gcd,
+    // Only meant for exact values, inexact ones are converted if finite
+    fn big(&self) -> BigRational {
+        match &self.repr {
+            Small { num, den } => BigRational::new_raw((*num).into(), (*den).into()),
+            Big(val) => val.as_ref().clone(),
+            Inexact(val) => BigRational::from_float(*val).unwrap_or_default(),
         }
     }
 
-    pub fn int(&self) -> isize {
-        self.num / self.den as isize
+    pub fn exact_zero(&self) -> bool {
+        matches!(self.repr, Small { num: 0, .. })
+    }
+
+    pub fn is_exact(&self) -> bool {
+        !matches!(self.repr, Inexact(_))
     }
 
-    // return Ok(Num(Frac::num(tk.parse::<isize>().unwrap())));
+    pub fn to_f64(&self) -> f64 {
+        match &self.repr {
+            Inexact(val) => *val,
+            _ => self.big().to_f64().unwrap_or(f64::NAN),
+        }
+    }
 
-    pub fn from_str(tk: &str) -> Option<Self> {
-        let frac = match tk.find("/") {
-            Some(v) => {
-                let num = match tk[0..v].parse::<isize>() {
-                    Ok(v) => v,
-                    Err(_) => return None,
-                };
-                let den = match tk[v + 1..tk.len()].parse::<usize>() {
-                    Ok(v) => v,
-                    Err(_) => return None,
-                };
-                Self { num, den }
+    pub fn to_inexact(&self) -> Frac {
+        Self::inexact(self.to_f64())
+    }
+
+    /// Exact value of the number, None for infinite and NaN
+    pub fn to_exact(&self) -> Option<Frac> {
+        match &self.repr {
+            Inexact(val) => BigRational::from_float(*val).map(Self::from_big),
+            _ => Some(self.clone()),
+        }
+    }
+
+    /// Simplest exact fraction differing from the number at most by the
+    /// tolerance, if not specified inexact numbers use the range of values
+    /// that round to the same float
+    pub fn rationalize(&self, tolerance: Option<&Frac>) -> Option<Frac> {
+        let val = self.to_exact()?.big();
+        let (lo, hi) = match (tolerance, &self.repr) {
+            (Some(tol), _) => {
+                let tol = tol.to_exact()?.big().abs();
+                (val.clone() - tol.clone(), val + tol)
             }
-            None => {
-                let num = match tk.parse::<isize>() {
-                    Ok(v) => v,
-                    Err(_) => return None,
-                };
-                Self::num(num)
+            (None, Inexact(f)) => {
+                let two = BigRational::from_integer(2.into());
+                let down = BigRational::from_float(f.next_down())?;
+                let up = BigRational::from_float(f.next_up())?;
+                ((val.clone() + down) / two.clone(), (val + up) / two)
             }
+            (None, _) => return Some(self.clone()),
+        };
+        Some(Self::from_big(simplest_between(lo, hi)))
+    }
+
+    /// Square root, exact if the number is the square of an exact fraction
+    pub fn sqrt(&self) -> Frac {
+        if self.is_exact() && !self.big().is_negative() {
+            let val = self.big();
+            let (num, den) = (val.numer().sqrt(), val.denom().sqrt());
+            if &(num.clone() * num.clone()) == val.numer()
+                && &(den.clone() * den.clone()) == val.denom()
+            {
+                return Self::from_big(BigRational::new_raw(num, den));
+            }
+        }
+        Self::inexact(self.to_f64().sqrt())
+    }
+
+    pub fn numer(&self) -> Frac {
+        match &self.repr {
+            Inexact(_) => self
+                .to_exact()
+                .map_or(self.clone(), |x| x.numer().to_inexact()),
+            _ => Self::from_big(BigRational::from_integer(self.big().numer().clone())),
+        }
+    }
+
+    pub fn denom(&self) -> Frac {
+        match &self.repr {
+            Inexact(_) => self
+                .to_exact()
+                .map_or(self.clone(), |x| x.denom().to_inexact()),
+            _ => Self::from_big(BigRational::from_integer(self.big().denom().clone())),
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        matches!(self.repr, Inexact(val) if val.is_nan())
+    }
+
+    /// Integer part of the number
+    pub fn trunc(&self) -> Frac {
+        match &self.repr {
+            // The denominator may not fit in isize
+            Small { num, den } => Self::num((*num as i128 / *den as i128) as isize),
+            Big(val) => Self::from_big(val.trunc()),
+            Inexact(val) => Self::inexact(val.trunc()),
+        }
+    }
+
+    /// Value of exact integers, None for anything else or if it does not fit
+    pub fn to_i128(&self) -> Option<i128> {
+        match &self.repr {
+            Small { num, den: 1 } => Some(*num as i128),
+            Big(val) if val.is_integer() => val.numer().to_i128(),
+            _ => None,
+        }
+    }
+
+    /// Integer part of the number, saturating to fit in isize
+    pub fn int(&self) -> isize {
+        match &self.repr {
+            Small { num, den } => (*num as i128 / *den as i128) as isize,
+            Big(val) => val
+                .to_integer()
+                .to_isize()
+                .unwrap_or(match val.is_negative() {
+                    true => isize::MIN,
+                    false => isize::MAX,
+                }),
+            Inexact(val) => *val as isize,
+        }
+    }
+}
+
+impl FromStr for Frac {
+    type Err = MalErr;
+
+    fn from_str(tk: &str) -> Result<Self, MalErr> {
+        let invalid = || MalErr::unrecoverable(format!("Cannot parse {tk} as a number").as_str());
+        if tk.contains(['.', 'e', 'E']) {
+            return tk.parse::<f64>().map(Self::inexact).map_err(|_| invalid());
+        }
+        let (num, den) = match tk.find('/') {
+            Some(v) => (&tk[0..v], &tk[v + 1..tk.len()]),
+            None => (tk, "1"),
         };
+        let num = num.parse::<BigInt>().map_err(|_| invalid())?;
+        let den = den.parse::<BigInt>().map_err(|_| invalid())?;
+        if den.is_zero() {
+            return Err(invalid());
+        }
         // Ensure that value is simplified before being inserted
         // otherwise
         // (/ 4 4)  results in 1/1
         // 4/4      results in 4/4
         // this breaks some functions (like ceil) and doesn't make much sense
-        Some(frac.simplify())
+        Ok(Self::from_big(BigRational::new(num, den)))
+    }
+}
+
+/// Position of a form in the file it was read from
+#[derive(Clone, Debug, PartialEq)]
+pub struct Loc {
+    pub file: MalStr,
+    pub line: usize,
+    pub col: usize,
+}
+
+impl fmt::Display for Loc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.col)
+    }
+}
+
+/// Function call recorded while an error unwinds, anonymous functions have
+/// no name
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub name: Option<MalStr>,
+    pub loc: Option<Rc<Loc>>,
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name.as_deref().unwrap_or("λ"))?;
+        match &self.loc {
+            Some(loc) => write!(f, " ({loc})"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Content of lists and vectors, forms read from a file also remember where
+/// they were found so that errors can point back to the source
+///
+/// Items are persistent and shared between all the values derived from them:
+/// they are kept in cons cells after "cons" and "rest", so that walking a list
+/// one element at a time costs O(1) per step, and in a trie otherwise, so that
+/// indexing costs O(log n)
+#[derive(Clone)]
+pub struct MalArgs {
+    items: Items,
+    loc: Option<Rc<Loc>>,
+}
+
+#[derive(Clone)]
+enum Items {
+    Cons(List<MalType>),
+    // Items of the trie from the offset on, so that dropping the first items
+    // does not copy the others
+    Trie(rpds::Vector<MalType>, usize),
+}
+
+/// Iterator over the items of a list or vector
+pub enum Iter<'a> {
+    Cons(rpds::list::Iter<'a, MalType, RcK>),
+    Trie(Skip<rpds::vector::Iter<'a, MalType, RcK>>),
+}
+
+impl<'a> Iterator for Iter<'a> {
+    type Item = &'a MalType;
+
+    fn next(&mut self) -> Option<&'a MalType> {
+        match self {
+            Self::Cons(iter) => iter.next(),
+            Self::Trie(iter) => iter.next(),
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self {
+            Self::Cons(iter) => iter.size_hint(),
+            Self::Trie(iter) => iter.size_hint(),
+        }
+    }
+}
+
+impl ExactSizeIterator for Iter<'_> {}
+
+impl MalArgs {
+    pub fn with_loc(mut self, loc: Option<Rc<Loc>>) -> Self {
+        self.loc = loc;
+        self
+    }
+
+    pub fn loc(&self) -> Option<&Rc<Loc>> {
+        self.loc.as_ref()
+    }
+
+    pub fn len(&self) -> usize {
+        match &self.items {
+            Items::Cons(list) => list.len(),
+            Items::Trie(vec, offset) => vec.len() - offset,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn iter(&self) -> Iter<'_> {
+        match &self.items {
+            Items::Cons(list) => Iter::Cons(list.iter()),
+            Items::Trie(vec, offset) => Iter::Trie(vec.iter().skip(*offset)),
+        }
+    }
+
+    pub fn first(&self) -> Option<&MalType> {
+        self.get(0)
+    }
+
+    pub fn last(&self) -> Option<&MalType> {
+        match &self.items {
+            Items::Cons(list) => list.last(),
+            Items::Trie(..) if self.is_empty() => None,
+            Items::Trie(vec, _) => vec.last(),
+        }
+    }
+
+    /// Item at the given position, cons cells are walked from the start
+    pub fn get(&self, index: usize) -> Option<&MalType> {
+        match &self.items {
+            Items::Cons(list) => list.iter().nth(index),
+            Items::Trie(vec, offset) => vec.get(offset + index),
+        }
+    }
+
+    /// Everything but the first n items, empty if there are not enough
+    pub fn skip(&self, n: usize) -> MalArgs {
+        let n = n.min(self.len());
+        let items = match &self.items {
+            Items::Cons(list) => {
+                let mut list = list.clone();
+                for _ in 0..n {
+                    list.drop_first_mut();
+                }
+                Items::Cons(list)
+            }
+            Items::Trie(vec, offset) => Items::Trie(vec.clone(), offset + n),
+        };
+        items.into()
+    }
+
+    /// Everything but the first item, empty if there is none
+    pub fn rest(&self) -> MalArgs {
+        self.skip(1)
+    }
+
+    /// First item, fails if there is none
+    pub fn car(&self) -> Result<&MalType, MalErr> {
+        self.first()
+            .ok_or_else(|| MalErr::unrecoverable("Expected at least one argument"))
+    }
+
+    /// First item and the rest, fails if there is none
+    pub fn car_cdr(&self) -> Result<(&MalType, MalArgs), MalErr> {
+        Ok((self.car()?, self.rest()))
+    }
+
+    /// New list with the value in front of the items
+    pub fn cons(&self, value: MalType) -> MalArgs {
+        let list = match &self.items {
+            Items::Cons(list) => list.push_front(value),
+            Items::Trie(..) => iter::once(value).chain(self.iter().cloned()).collect(),
+        };
+        Items::Cons(list).into()
+    }
+
+    /// New vector with the value after the items
+    pub fn push_back(&self, value: MalType) -> MalArgs {
+        let vec = match &self.items {
+            Items::Trie(vec, 0) => vec.push_back(value),
+            _ => self.iter().cloned().chain(iter::once(value)).collect(),
+        };
+        Items::Trie(vec, 0).into()
+    }
+
+    pub fn to_vec(&self) -> Vec<MalType> {
+        self.iter().cloned().collect()
+    }
+}
+
+impl Default for MalArgs {
+    fn default() -> Self {
+        Items::Trie(rpds::Vector::new(), 0).into()
+    }
+}
+
+impl From<Items> for MalArgs {
+    fn from(items: Items) -> Self {
+        Self { items, loc: None }
+    }
+}
+
+impl Index<usize> for MalArgs {
+    type Output = MalType;
+
+    fn index(&self, index: usize) -> &MalType {
+        self.get(index).unwrap_or_else(|| {
+            panic!(
+                "index out of bounds: the len is {} but the index is {index}",
+                self.len()
+            )
+        })
+    }
+}
+
+impl<'a> IntoIterator for &'a MalArgs {
+    type Item = &'a MalType;
+    type IntoIter = Iter<'a>;
+
+    fn into_iter(self) -> Iter<'a> {
+        self.iter()
+    }
+}
+
+impl From<Vec<MalType>> for MalArgs {
+    fn from(items: Vec<MalType>) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+impl From<&[MalType]> for MalArgs {
+    fn from(items: &[MalType]) -> Self {
+        items.iter().cloned().collect()
+    }
+}
+
+impl<const N: usize> From<[MalType; N]> for MalArgs {
+    fn from(items: [MalType; N]) -> Self {
+        items.into_iter().collect()
+    }
+}
+
+impl FromIterator<MalType> for MalArgs {
+    fn from_iter<I: IntoIterator<Item = MalType>>(iter: I) -> Self {
+        Items::Trie(iter.into_iter().collect(), 0).into()
+    }
+}
+
+type NativeBody = dyn Fn(&[MalType]) -> MalRet;
+
+/// Native function that can capture state, used by programs embedding mal to
+/// expose their own objects
+#[derive(Clone)]
+pub struct NativeFn {
+    pub name: MalStr,
+    pub help: MalStr,
+    // Number of arguments accepted, variadic functions have no maximum
+    pub min_args: usize,
+    pub max_args: Option<usize>,
+    fun: Rc<NativeBody>,
+}
+
+impl NativeFn {
+    /// Function accepting any number of arguments
+    pub fn new(name: &str, help: &str, fun: impl Fn(&[MalType]) -> MalRet + 'static) -> Self {
+        Self {
+            name: name.into(),
+            help: help.into(),
+            min_args: 0,
+            max_args: None,
+            fun: Rc::new(fun),
+        }
+    }
+
+    pub fn with_arity(mut self, min_args: usize, max_args: Option<usize>) -> Self {
+        self.min_args = min_args;
+        self.max_args = max_args;
+        self
+    }
+
+    /// Describe the number of arguments accepted
+    pub fn arity(&self) -> String {
+        match self.max_args {
+            None if self.min_args == 0 => "any number of".to_string(),
+            None => format!("at least {}", self.min_args),
+            Some(max) if max == self.min_args => max.to_string(),
+            Some(max) => format!("{} to {}", self.min_args, max),
+        }
+    }
+
+    pub fn call(&self, args: &[MalType]) -> MalRet {
+        if args.len() < self.min_args || self.max_args.is_some_and(|max| args.len() > max) {
+            return Err(MalErr::unrecoverable(
+                format!(
+                    "{}: expected {} args, got {}",
+                    self.name,
+                    self.arity(),
+                    args.len()
+                )
+                .as_str(),
+            ));
+        }
+        (self.fun)(args)
+    }
+}
+
+impl From<NativeFn> for MalType {
+    fn from(fun: NativeFn) -> Self {
+        MalType::Native(Rc::new(fun))
     }
 }
 
@@ -174,12 +708,18 @@ pub enum MalType {
     List(MalArgs),
     Vector(MalArgs),
     Map(MalMap),
+    Set(MalSet),
     Fun(fn(&[MalType]) -> MalRet, &'static str), // Used for base functions, implemented using the underlying language (rust)
+    Native(Rc<NativeFn>), // Same as Fun, but can capture state (registered by programs embedding mal)
     MalFun {
         // eval: fn(ast: &MalType, env: Env) -> MalRet,
         params: Rc<MalType>,
         ast: Rc<MalType>,
         env: Env,
+        is_macro: bool,
+        name: Option<MalStr>,
+        // Missing arguments are an error, see "*STRICT-ARITY*"
+        strict: bool,
     }, // Used for functions defined within mal
     // Use Rc so I can now clone like there's no tomorrow
     Sym(MalStr),
@@ -188,6 +728,8 @@ pub enum MalType {
     Ch(char),
     Num(Frac),
     Atom(Rc<RefCell<MalType>>),
+    Port(Rc<Port>),
+    Regex(Rc<Pattern>),
     Nil,
     T,
 }
@@ -201,14 +743,14 @@ impl Default for &MalType {
 impl MalType {
     pub fn if_number(&self) -> Result<Frac, MalErr> {
         match self {
-            Self::Num(val) => Ok(*val),
+            Self::Num(val) => Ok(val.clone()),
             _ => Err(MalErr::unrecoverable(
                 format!("{:?} is not a number", prt(self)).as_str(),
             )),
         }
     }
 
-    pub fn if_list(&self) -> Result<&[MalType], MalErr> {
+    pub fn if_list(&self) -> Result<&MalArgs, MalErr> {
         match self {
             Self::List(list) | Self::Vector(list) => Ok(list),
             _ => Err(MalErr::unrecoverable(
@@ -217,7 +759,7 @@ impl MalType {
         }
     }
 
-    pub fn if_vec(&self) -> Result<&[MalType], MalErr> {
+    pub fn if_vec(&self) -> Result<&MalArgs, MalErr> {
         match self {
             Self::Vector(list) => Ok(list),
             _ => Err(MalErr::unrecoverable(
@@ -244,21 +786,47 @@ impl MalType {
         }
     }
 
+    /// Name anonymous functions after the symbol they are assigned to
+    pub fn with_name(self, sym: &str) -> MalType {
+        match self {
+            Self::MalFun {
+                params,
+                ast,
+                env,
+                is_macro,
+                name: None,
+                strict,
+            } => Self::MalFun {
+                params,
+                ast,
+                env,
+                is_macro,
+                name: Some(sym.into()),
+                strict,
+            },
+            _ => self,
+        }
+    }
+
     pub fn label_type(&self) -> MalType {
-        Key(("ʞ:".to_owned()
+        Key((":".to_owned()
             + match self {
                 M::Nil => "nil",
                 M::T => "t",
                 M::Num(_) => "number",
-                M::Fun(_, _) | M::MalFun { .. } => "lambda",
+                M::MalFun { is_macro: true, .. } => "macro",
+                M::Fun(_, _) | M::Native(_) | M::MalFun { .. } => "lambda",
                 M::Key(_) => "key",
                 M::Str(_) => "string",
                 M::Sym(_) => "symbol",
                 M::List(_) => "list",
                 M::Vector(_) => "vector",
                 M::Map(_) => "map",
+                M::Set(_) => "set",
                 M::Atom(_) => "atom",
                 M::Ch(_) => "char",
+                M::Port(_) => "port",
+                M::Regex(_) => "regex",
             })
         .into())
     }
@@ -267,26 +835,101 @@ impl MalType {
 use crate::types::MalType as M;
 
 // That's a quite chonky function
-fn mal_compare(args: (&MalType, &MalType)) -> bool {
+// As keys NaN must be equal to itself, or it could never be found again
+fn mal_compare(args: (&MalType, &MalType), keys: bool) -> bool {
+    let compare = |args| mal_compare(args, keys);
     match (args.0, args.1) {
         (M::Nil, M::Nil) => true,
         (M::T, M::T) => true,
-        (M::Num(a), M::Num(b)) => a == b,
+        (M::Num(a), M::Num(b)) => a == b || (keys && a.is_nan() && b.is_nan()),
         (M::Ch(a), M::Ch(b)) => a == b,
         (M::Key(a), M::Key(b)) | (M::Str(a), M::Str(b)) | (M::Sym(a), M::Sym(b)) => a == b,
+        (M::Regex(a), M::Regex(b)) => a.as_str() == b.as_str(),
         (M::List(a), M::List(b)) | (M::Vector(a), M::Vector(b)) => {
-            a.len() == b.len() && a.iter().zip(b.iter()).all(mal_compare)
+            a.len() == b.len() && a.iter().zip(b.iter()).all(compare)
+        }
+        (M::Map(a), M::Map(b)) => {
+            a.size() == b.size()
+                && a.iter()
+                    .all(|(k, v)| b.get(k).is_some_and(|w| compare((v, w))))
         }
+        (M::Set(a), M::Set(b)) => a.size() == b.size() && a.iter().all(|x| b.contains(x)),
+        // Mutable values and functions are only equal to themselves
+        (M::Atom(a), M::Atom(b)) => Rc::ptr_eq(a, b),
+        (M::Port(a), M::Port(b)) => Rc::ptr_eq(a, b),
+        (M::Fun(a, _), M::Fun(b, _)) => std::ptr::fn_addr_eq(*a, *b),
+        (M::Native(a), M::Native(b)) => Rc::ptr_eq(a, b),
+        (
+            M::MalFun {
+                params: ap,
+                ast: aa,
+                env: ae,
+                is_macro: am,
+                ..
+            },
+            M::MalFun {
+                params: bp,
+                ast: ba,
+                env: be,
+                is_macro: bm,
+                ..
+            },
+        ) => Rc::ptr_eq(ap, bp) && Rc::ptr_eq(aa, ba) && Rc::ptr_eq(ae, be) && am == bm,
         _ => false,
     }
 }
 
+// Items are hashed on their own and summed, so that their order does not
+// matter
+fn unordered_hash<T: Hash>(items: impl Iterator<Item = T>) -> u64 {
+    items
+        .map(|item| {
+            let mut hasher = DefaultHasher::new();
+            item.hash(&mut hasher);
+            hasher.finish()
+        })
+        .fold(0, u64::wrapping_add)
+}
+
+// Values are compared as "=" does, so that any value can be a map key, except
+// for NaN that is equal to itself
+impl PartialEq for MalType {
+    fn eq(&self, other: &Self) -> bool {
+        mal_compare((self, other), true)
+    }
+}
+
+impl Eq for MalType {}
+
+impl Hash for MalType {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        mem::discriminant(self).hash(state);
+        match self {
+            M::Num(val) => val.hash(state),
+            M::Ch(c) => c.hash(state),
+            M::Key(s) | M::Str(s) | M::Sym(s) => s.hash(state),
+            M::Regex(re) => re.as_str().hash(state),
+            M::List(list) | M::Vector(list) => {
+                list.len().hash(state);
+                list.iter().for_each(|item| item.hash(state));
+            }
+            M::Map(map) => unordered_hash(map.iter()).hash(state),
+            M::Set(set) => unordered_hash(set.iter()).hash(state),
+            M::Atom(a) => Rc::as_ptr(a).hash(state),
+            M::Port(port) => Rc::as_ptr(port).hash(state),
+            M::Native(fun) => Rc::as_ptr(fun).hash(state),
+            M::MalFun { ast, .. } => Rc::as_ptr(ast).hash(state),
+            M::Fun(..) | M::Nil | M::T => (),
+        }
+    }
+}
+
 pub fn mal_equals(args: &[MalType]) -> MalRet {
     Ok(match args.len() {
         0 => M::T,
         _ => {
             let (car, cdr) = car_cdr(args)?;
-            if cdr.iter().all(|x| mal_compare((car, x))) {
+            if cdr.iter().all(|x| mal_compare((car, x), false)) {
                 M::T
             } else {
                 M::Nil
@@ -315,23 +958,110 @@ pub fn reset_bang(args: &[MalType]) -> MalRet {
 pub enum Severity {
     Recoverable,
     Unrecoverable,
+    // Request to quit with the given status, never caught by mal code
+    Exit(i32),
+}
+
+impl fmt::Debug for MalType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", prt(self))
+    }
 }
 
 #[derive(Debug)]
 pub struct MalErr {
     message: String,
     severity: Severity,
+    value: Option<MalType>,
+    loc: Option<Rc<Loc>>,
+    trace: Vec<Frame>,
 }
 
 impl MalErr {
     pub fn new(message: String, severity: Severity) -> Self {
-        Self { message, severity }
+        Self {
+            message,
+            severity,
+            value: None,
+            loc: None,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Error carrying a value raised from mal, strings are used as message
+    pub fn raise(value: MalType) -> Self {
+        let message = match &value {
+            Str(s) => s.to_string(),
+            _ => prt(&value),
+        };
+        Self {
+            message,
+            severity: Severity::Unrecoverable,
+            value: Some(value),
+            loc: None,
+            trace: Vec::new(),
+        }
+    }
+
+    /// Returns the value to bind in catch* clauses: the raised value if the
+    /// error comes from "raise", otherwise a map with message and kind
+    pub fn value(&self) -> MalType {
+        match &self.value {
+            Some(value) => value.clone(),
+            None => {
+                let kind = match self.severity {
+                    Severity::Recoverable => ":recoverable",
+                    Severity::Unrecoverable | Severity::Exit(_) => ":unrecoverable",
+                };
+                let mut map = MalMap::new();
+                map.insert_mut(Key(":message".into()), Str(self.message.as_str().into()));
+                map.insert_mut(Key(":kind".into()), Key(kind.into()));
+                if let Some(loc) = &self.loc {
+                    map.insert_mut(Key(":file".into()), Str(loc.file.clone()));
+                    map.insert_mut(Key(":line".into()), Num(Frac::num(loc.line as isize)));
+                    map.insert_mut(Key(":col".into()), Num(Frac::num(loc.col as isize)));
+                }
+                let trace = self.trace.iter().map(|f| Str(f.to_string().into()));
+                map.insert_mut(Key(":trace".into()), Vector(trace.collect()));
+                Map(map)
+            }
+        }
     }
 
     pub fn message(&self) -> String {
         self.message.to_string()
     }
 
+    /// Position of the innermost form that caused the error, if known
+    pub fn loc(&self) -> Option<&Loc> {
+        self.loc.as_deref()
+    }
+
+    /// Set the position of the error, unless a more precise one is known
+    pub fn at(mut self, loc: Option<&Rc<Loc>>) -> Self {
+        if self.loc.is_none() {
+            self.loc = loc.cloned();
+        }
+        self
+    }
+
+    /// Calls the error went through, innermost first
+    pub fn trace(&self) -> &[Frame] {
+        &self.trace
+    }
+
+    /// Record a call the error is unwinding through
+    pub fn traced(mut self, frame: Frame) -> Self {
+        self.trace.push(frame);
+        self
+    }
+
+    /// Prepend some context to the message, keeping everything else
+    pub fn prefixed(mut self, prefix: &str) -> Self {
+        self.message = format!("{prefix}: {}", self.message);
+        self
+    }
+
     pub fn severity(&self) -> Severity {
         self.severity
     }
@@ -340,11 +1070,23 @@ impl MalErr {
         self.severity == Severity::Recoverable
     }
 
+    /// Status requested by "exit", if this error is one
+    pub fn exit_code(&self) -> Option<i32> {
+        match self.severity {
+            Severity::Exit(code) => Some(code),
+            _ => None,
+        }
+    }
+
     pub fn severe(mut self) -> Self {
         self.severity = Severity::Unrecoverable;
         self
     }
 
+    pub fn exit(code: i32) -> Self {
+        Self::new(format!("exit with status {code}"), Severity::Exit(code))
+    }
+
     pub fn recoverable(message: &str) -> Self {
         Self::new(message.to_owned(), Severity::Recoverable)
     }
@@ -354,28 +1096,30 @@ impl MalErr {
     }
 }
 
+impl std::error::Error for MalErr {}
+
+impl fmt::Display for MalErr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.loc {
+            Some(loc) => write!(f, "{}: {}", loc, self.message),
+            None => write!(f, "{}", self.message),
+        }
+    }
+}
+
 use crate::printer::prt;
-use MalType::{Key, Map, Str};
+use MalType::{Key, Map, Num, Str, Vector};
 
 pub fn make_map(list: MalArgs) -> MalRet {
-    if list.len() % 2 != 0 {
+    if !list.len().is_multiple_of(2) {
         return Err(MalErr::unrecoverable("Map length is odd: missing value"));
     }
 
     let mut map = MalMap::new();
 
-    for i in (0..list.len()).step_by(2) {
-        match &list[i] {
-            Key(k) | Str(k) => {
-                let v = &list[i + 1];
-                map.insert(k.clone(), v.clone());
-            }
-            _ => {
-                return Err(MalErr::unrecoverable(
-                    format!("Map key not valid: {}", prt(&list[i])).as_str(),
-                ))
-            }
-        }
+    let mut items = list.iter().cloned();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        map.insert_mut(key, value);
     }
     Ok(Map(map))
 }
@@ -392,13 +1136,31 @@ pub fn escape_str(s: &str) -> String {
     )
 }
 
+// Escapes are read one at a time, "\\n" is a backslash followed by "n"
 pub fn unescape_str(s: &str) -> String {
-    String::from(&s[1..s.len() - 1])
-        .replace("\\\\", "\\")
-        .replace("\\n", "\n")
-        .replace("\\r", "\r")
-        .replace("\\t", "\t")
-        .replace("\\\"", "\"")
+    let mut ret = String::new();
+    let mut chars = s[1..s.len() - 1].chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            ret.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => ret.push('\n'),
+            Some('r') => ret.push('\r'),
+            Some('t') => ret.push('\t'),
+            Some('u') => {
+                let code = chars.by_ref().take(4).collect::<String>();
+                match u32::from_str_radix(&code, 16).ok().and_then(char::from_u32) {
+                    Some(c) => ret.push(c),
+                    None => ret.push_str(&format!("\\u{code}")),
+                }
+            }
+            Some(c) => ret.push(c),
+            None => ret.push('\\'),
+        }
+    }
+    ret
 }
 
 ////////////////////////////////////////////////////////////////////////////////
@@ -425,5 +1187,82 @@ mod tests {
         assert_eq!(unescape_str("\"\\\\\""), "\\"); // unescape "\"
         assert_eq!(unescape_str("\"\\n\""), "\n"); // unescape "\n"
         assert_eq!(unescape_str("\"\\\"\""), "\""); // unescape "\""
+        assert_eq!(unescape_str("\"\\\\n\""), "\\n"); // escaped "\" before "n"
+        assert_eq!(unescape_str("\"\\u00e8\""), "è"); // unicode escape
+    }
+
+    #[test]
+    fn hash_eq() {
+        use crate::types::{Frac, MalType as M};
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let hash = |v: &M| {
+            let mut hasher = DefaultHasher::new();
+            v.hash(&mut hasher);
+            hasher.finish()
+        };
+        let pairs = [
+            (M::Num(Frac::num(1)), M::Num(Frac::inexact(1.0))),
+            (M::Num(Frac::inexact(0.0)), M::Num(Frac::inexact(-0.0))),
+            (
+                M::Map(
+                    [(M::Num(Frac::num(1)), M::Nil), (M::Key(":a".into()), M::T)]
+                        .into_iter()
+                        .collect(),
+                ),
+                M::Map(
+                    [(M::Key(":a".into()), M::T), (M::Num(Frac::num(1)), M::Nil)]
+                        .into_iter()
+                        .collect(),
+                ),
+            ),
+            (
+                M::Vector([M::Str("a".into())].into()),
+                M::Vector([M::Str("a".into())].into()),
+            ),
+            // NaN keys can be found again
+            (
+                M::Num(Frac::inexact(f64::NAN)),
+                M::Num(Frac::inexact(-f64::NAN)),
+            ),
+            (
+                M::Vector([M::Num(Frac::inexact(f64::NAN))].into()),
+                M::Vector([M::Num(Frac::inexact(f64::NAN))].into()),
+            ),
+        ];
+        // Equal values must have the same hash
+        for (a, b) in pairs {
+            assert!(a == b);
+            assert_eq!(hash(&a), hash(&b));
+        }
+        assert!(M::Key(":a".into()) != M::Str(":a".into()));
+        assert!(M::List([].into()) != M::Vector([].into()));
+    }
+
+    #[test]
+    fn mal_args() {
+        use crate::types::{Frac, MalArgs, MalType as M};
+
+        let num = |n| M::Num(Frac::num(n));
+        let vec: MalArgs = (1..=4).map(num).collect();
+        // Dropping items from a trie, then adding them as cons cells
+        let rest = vec.skip(2);
+        assert_eq!(rest.len(), 2);
+        assert_eq!(rest.first(), Some(&num(3)));
+        let list = rest.cons(num(2)).cons(num(1));
+        assert_eq!(list.to_vec(), vec.to_vec());
+        assert_eq!(list.get(3), Some(&num(4)));
+        assert_eq!(list.last(), Some(&num(4)));
+        assert!(list.skip(5).is_empty() && vec.skip(5).is_empty());
+        assert!(MalArgs::default().car_cdr().is_err());
+        assert_eq!(list.push_back(num(5)).get(4), Some(&num(5)));
+        assert_eq!(
+            rest.push_back(num(5)).to_vec(),
+            vec.skip(2).push_back(num(5)).to_vec()
+        );
+        // The original items are not affected
+        assert_eq!(vec.len(), 4);
+        assert_eq!(rest.len(), 2);
     }
 }